@@ -0,0 +1,87 @@
+//! `raffi bench`: time the stages raffi goes through before the menu shows up,
+//! with the current configuration and caches, to spot what slows it down.
+
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::{
+    entry_icon_name, get_icon_map, is_icon_file, is_remote_icon, load_config, make_fuzzel_input,
+    read_config, resolve_icons, Args,
+};
+
+/// Default number of runs of each stage.
+pub const DEFAULT_RUNS: usize = 10;
+
+/// Run a stage `runs` times and return the duration of each run.
+fn time_stage(runs: usize, mut stage: impl FnMut() -> Result<()>) -> Result<Vec<Duration>> {
+    let mut timings = Vec::with_capacity(runs);
+    for _ in 0..runs {
+        let start = Instant::now();
+        stage()?;
+        timings.push(start.elapsed());
+    }
+    timings.sort();
+    Ok(timings)
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Time every stage and print min, median and max in milliseconds.
+pub fn run(configfile: &str, args: &Args, runs: usize) -> Result<()> {
+    let runs = runs.max(1);
+    let mut results = vec![
+        (
+            "parse config",
+            time_stage(runs, || load_config(configfile).map(drop))?,
+        ),
+        (
+            "check conditions",
+            time_stage(runs, || read_config(configfile, args, true).map(drop))?,
+        ),
+    ];
+
+    let mut rafficonfigs = read_config(configfile, args, true)?;
+    if !args.no_icons {
+        let wanted: HashSet<String> = rafficonfigs
+            .iter()
+            .map(entry_icon_name)
+            .filter(|icon| !is_remote_icon(icon) && !is_icon_file(icon))
+            .collect();
+        results.push((
+            "scan icons",
+            time_stage(runs, || get_icon_map(&wanted).map(drop))?,
+        ));
+        // the first resolution fills the cache, the timed ones read it
+        resolve_icons(&mut rafficonfigs)?;
+        results.push((
+            "cached icons",
+            time_stage(runs, || resolve_icons(&mut rafficonfigs))?,
+        ));
+    }
+    results.push((
+        "menu input",
+        time_stage(runs, || {
+            make_fuzzel_input(&rafficonfigs, args.no_icons);
+            Ok(())
+        })?,
+    ));
+
+    println!("{} entries, {} runs per stage", rafficonfigs.len(), runs);
+    println!("{:<18}{:>10}{:>10}{:>10}", "stage", "min", "median", "max");
+    for (stage, timings) in results {
+        println!(
+            "{:<18}{:>8.2}ms{:>8.2}ms{:>8.2}ms",
+            stage,
+            millis(timings[0]),
+            millis(timings[timings.len() / 2]),
+            millis(timings[timings.len() - 1])
+        );
+    }
+    Ok(())
+}
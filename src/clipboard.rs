@@ -0,0 +1,122 @@
+//! Clipboard history: the entries of a clipboard manager like cliphist, the
+//! chosen one being decoded and copied back to the clipboard.
+
+use std::{path::Path, process::Command};
+
+use anyhow::{Context, Result};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{shell_quote, RaffiConfig};
+
+/// The commands used for the clipboard history, run with `sh -c`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+pub struct ClipboardSettings {
+    /// Print the history, one `id<TAB>preview` line per entry.
+    pub list: String,
+    /// Read a history line on stdin and print its content.
+    pub decode: String,
+    /// Copy its stdin to the clipboard.
+    pub copy: String,
+}
+
+impl Default for ClipboardSettings {
+    fn default() -> Self {
+        ClipboardSettings {
+            list: "cliphist list".to_string(),
+            decode: "cliphist decode".to_string(),
+            copy: "wl-copy".to_string(),
+        }
+    }
+}
+
+/// Placeholder of the args replaced with the clipboard content.
+pub const PLACEHOLDER: &str = "{clipboard}";
+
+/// Environment variable the clipboard content is given to the command in.
+pub const ENV: &str = "RAFFI_CLIPBOARD";
+
+/// Shells and interpreters running the code given after `-c` or `-e`.
+const INTERPRETERS: &[&str] = &[
+    "sh", "bash", "zsh", "dash", "ksh", "fish", "nu", "python", "python3", "node", "perl", "ruby",
+];
+
+/// Whether a command runs code from its args, where the clipboard content
+/// would become code.
+pub fn runs_inline_code(binary: &str, args: &[String]) -> bool {
+    let name = Path::new(binary)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(binary);
+    // short flags can be grouped, as in `sh -euc`
+    let inline_flag = |arg: &String| {
+        arg == "--eval"
+            || arg
+                .strip_prefix('-')
+                .is_some_and(|flags| !flags.starts_with('-') && flags.contains(['c', 'e']))
+    };
+    INTERPRETERS.contains(&name) && args.iter().any(inline_flag)
+}
+
+/// Return the content of the clipboard, read with wl-paste on Wayland and
+/// xclip otherwise.
+pub fn contents() -> Result<String> {
+    let (program, args): (&str, &[&str]) = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        ("wl-paste", &["--no-newline"])
+    } else {
+        ("xclip", &["-selection", "clipboard", "-o"])
+    };
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .context(format!("cannot run {}", program))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "cannot read the clipboard, {} failed with {}",
+            program,
+            output.status
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Return an entry for every line of the clipboard history, images being
+/// shown with an image icon.
+pub fn entries(settings: &ClipboardSettings) -> Result<Vec<RaffiConfig>> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&settings.list)
+        .output()
+        .context(format!("cannot run {}", settings.list))?;
+    if !output.status.success() {
+        anyhow::bail!("{} failed with {}", settings.list, output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (id, preview) = line.split_once('\t').unwrap_or((line, line));
+            let icon = if preview.starts_with("[[ binary data") {
+                "image-x-generic"
+            } else {
+                "edit-paste"
+            };
+            RaffiConfig {
+                binary: Some("sh".to_string()),
+                script: Some(format!(
+                    "printf '%s\\n' {} | {} | {}",
+                    shell_quote(line),
+                    settings.decode,
+                    settings.copy
+                )),
+                description: Some(preview.to_string()),
+                icon: Some(icon.to_string()),
+                name: format!("clipboard-{}", id),
+                ..Default::default()
+            }
+        })
+        .collect())
+}
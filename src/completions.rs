@@ -0,0 +1,337 @@
+//! Shell completion scripts generated from the gumdrop usage messages, so
+//! they follow the options and subcommands without being kept in sync by hand.
+
+use anyhow::Result;
+
+/// Shells we know how to generate completions for.
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+/// A command-line option parsed from a gumdrop usage message.
+#[derive(Default)]
+struct CliOption {
+    short: Option<char>,
+    long: Option<String>,
+    value: Option<String>,
+    help: String,
+}
+
+impl CliOption {
+    /// Whether the option value is a path and should complete file names.
+    fn takes_file(&self) -> bool {
+        self.value
+            .as_deref()
+            .is_some_and(|meta| meta.contains("FILE"))
+    }
+}
+
+/// A subcommand with its own options.
+struct CliCommand {
+    name: String,
+    help: String,
+    options: Vec<CliOption>,
+}
+
+/// Parse the option lines of a gumdrop usage message.
+fn parse_options(usage: &str) -> Vec<CliOption> {
+    let mut options: Vec<CliOption> = Vec::new();
+    for line in usage.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('-') {
+            let (spec, help) = trimmed.split_once("  ").unwrap_or((trimmed, ""));
+            let mut option = CliOption {
+                help: help.trim().to_string(),
+                ..Default::default()
+            };
+            for token in spec.split_whitespace() {
+                let token = token.trim_end_matches(',');
+                if let Some(long) = token.strip_prefix("--") {
+                    option.long = Some(long.to_string());
+                } else if let Some(short) = token.strip_prefix('-') {
+                    option.short = short.chars().next();
+                } else {
+                    option.value = Some(token.to_string());
+                }
+            }
+            options.push(option);
+        } else if let Some(last) = options.last_mut() {
+            // long option specs push their help message to the next line
+            if last.help.is_empty() && line.starts_with(' ') {
+                last.help = trimmed.trim().to_string();
+            }
+        }
+    }
+    options
+}
+
+/// Parse the subcommands from a gumdrop command list.
+fn parse_commands(
+    command_list: &str,
+    command_usage: fn(&str) -> Option<&'static str>,
+) -> Vec<CliCommand> {
+    command_list
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let (name, help) = line.split_once(' ').unwrap_or((line, ""));
+            (!name.is_empty()).then(|| CliCommand {
+                name: name.to_string(),
+                help: help.trim().to_string(),
+                options: parse_options(command_usage(name).unwrap_or_default()),
+            })
+        })
+        .collect()
+}
+
+/// Generate the completion script for a shell.
+pub fn generate(
+    shell: &str,
+    usage: &str,
+    command_list: &str,
+    command_usage: fn(&str) -> Option<&'static str>,
+) -> Result<String> {
+    let options = parse_options(usage);
+    let commands = parse_commands(command_list, command_usage);
+    match shell {
+        "bash" => Ok(bash(&options, &commands)),
+        "zsh" => Ok(zsh(&options, &commands)),
+        "fish" => Ok(fish(&options, &commands)),
+        _ => anyhow::bail!(
+            "unsupported shell {}, use one of: {}",
+            shell,
+            SHELLS.join(", ")
+        ),
+    }
+}
+
+/// Words completed as free arguments of a subcommand.
+enum CommandValues {
+    Shells,
+    Entries,
+}
+
+fn command_values(command: &str) -> Option<CommandValues> {
+    match command {
+        "completions" => Some(CommandValues::Shells),
+        "run" => Some(CommandValues::Entries),
+        _ => None,
+    }
+}
+
+fn option_words(option: &CliOption) -> Vec<String> {
+    let mut words = Vec::new();
+    if let Some(short) = option.short {
+        words.push(format!("-{}", short));
+    }
+    if let Some(long) = &option.long {
+        words.push(format!("--{}", long));
+    }
+    words
+}
+
+fn bash(options: &[CliOption], commands: &[CliCommand]) -> String {
+    let flatten = |options: &[CliOption]| -> String {
+        options
+            .iter()
+            .flat_map(option_words)
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let with_files: Vec<String> = options
+        .iter()
+        .filter(|o| o.takes_file())
+        .flat_map(option_words)
+        .collect();
+    let with_values: Vec<String> = options
+        .iter()
+        .filter(|o| o.value.is_some() && !o.takes_file())
+        .flat_map(option_words)
+        .collect();
+    let names: Vec<&str> = commands.iter().map(|c| c.name.as_str()).collect();
+
+    let mut script = String::from(
+        r#"# bash completion for raffi
+_raffi_entries() {
+    local i config=()
+    for ((i = 1; i < ${#COMP_WORDS[@]} - 1; i++)); do
+        case ${COMP_WORDS[i]} in
+            -c|--configfile) config=(-c "${COMP_WORDS[i+1]}") ;;
+        esac
+    done
+    raffi "${config[@]}" completions --entries 2>/dev/null
+}
+
+_raffi() {
+    local cur=${COMP_WORDS[COMP_CWORD]} prev=${COMP_WORDS[COMP_CWORD-1]}
+    local word cmd=""
+    for word in "${COMP_WORDS[@]:1:COMP_CWORD-1}"; do
+        case $word in
+"#,
+    );
+    if !names.is_empty() {
+        script.push_str(&format!("            {}) cmd=$word ;;\n", names.join("|")));
+    }
+    script.push_str("        esac\n    done\n\n    case $prev in\n");
+    if !with_files.is_empty() {
+        script.push_str(&format!(
+            "        {}) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;\n",
+            with_files.join("|")
+        ));
+    }
+    if !with_values.is_empty() {
+        script.push_str(&format!("        {}) return ;;\n", with_values.join("|")));
+    }
+    script.push_str("    esac\n\n    case $cmd in\n");
+    for command in commands {
+        let values = match command_values(&command.name) {
+            Some(CommandValues::Shells) => SHELLS.join(" "),
+            Some(CommandValues::Entries) => "$(_raffi_entries)".to_string(),
+            None => String::new(),
+        };
+        script.push_str(&format!(
+            "        {}) COMPREPLY=($(compgen -W \"{} {}\" -- \"$cur\")) ;;\n",
+            command.name,
+            flatten(&command.options),
+            values
+        ));
+    }
+    script.push_str(&format!(
+        "        *) COMPREPLY=($(compgen -W \"{} {}\" -- \"$cur\")) ;;\n    esac\n}}\n\ncomplete -F _raffi raffi\n",
+        flatten(options),
+        names.join(" ")
+    ));
+    script
+}
+
+/// Escape a help message for a zsh `_arguments` spec.
+fn zsh_escape(help: &str) -> String {
+    help.replace('\\', "\\\\")
+        .replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh_specs(options: &[CliOption]) -> Vec<String> {
+    options
+        .iter()
+        .map(|option| {
+            let words = option_words(option);
+            let action = match (&option.value, option.takes_file()) {
+                (Some(meta), true) => format!(":{}:_files", meta.to_lowercase()),
+                (Some(meta), false) => format!(":{}: ", meta.to_lowercase()),
+                (None, _) => String::new(),
+            };
+            let help = zsh_escape(&option.help);
+            if words.len() > 1 {
+                format!(
+                    "'({})'{{{}}}'[{}]{}'",
+                    words.join(" "),
+                    words.join(","),
+                    help,
+                    action
+                )
+            } else {
+                format!("'{}[{}]{}'", words.join(""), help, action)
+            }
+        })
+        .collect()
+}
+
+fn zsh(options: &[CliOption], commands: &[CliCommand]) -> String {
+    let mut script =
+        String::from("#compdef raffi\n\n_raffi() {\n    local -a commands\n    commands=(\n");
+    for command in commands {
+        script.push_str(&format!(
+            "        '{}:{}'\n",
+            command.name,
+            command.help.replace('\'', "'\\''").replace(':', "\\:")
+        ));
+    }
+    script.push_str("    )\n\n    local curcontext=$curcontext state line\n    _arguments -C \\\n");
+    for spec in zsh_specs(options) {
+        script.push_str(&format!("        {} \\\n", spec));
+    }
+    script.push_str(
+        "        '1: :->command' \\\n        '*:: :->args'\n\n    case $state in\n        command) _describe 'command' commands ;;\n        args)\n            case $words[1] in\n",
+    );
+    for command in commands {
+        let mut specs = zsh_specs(&command.options);
+        match command_values(&command.name) {
+            Some(CommandValues::Shells) => specs.push(format!("'1:shell:({})'", SHELLS.join(" "))),
+            Some(CommandValues::Entries) => specs.push("'1:entry:_raffi_entries'".to_string()),
+            None => {}
+        }
+        script.push_str(&format!(
+            "                {}) _arguments {} ;;\n",
+            command.name,
+            specs.join(" ")
+        ));
+    }
+    script.push_str(
+        r#"            esac
+            ;;
+    esac
+}
+
+_raffi_entries() {
+    local -a config entries
+    local i=${words[(I)-c|--configfile]}
+    (( i )) && config=(-c ${words[i+1]})
+    entries=(${(f)"$(raffi $config completions --entries 2>/dev/null)"})
+    _describe 'entry' entries
+}
+
+_raffi "$@"
+"#,
+    );
+    script
+}
+
+fn fish(options: &[CliOption], commands: &[CliCommand]) -> String {
+    let escape = |help: &str| help.replace('\\', "\\\\").replace('\'', "\\'");
+    let line = |condition: &str, option: &CliOption| {
+        let mut line = format!("complete -c raffi -n '{}'", condition);
+        if let Some(short) = option.short {
+            line.push_str(&format!(" -s {}", short));
+        }
+        if let Some(long) = &option.long {
+            line.push_str(&format!(" -l {}", long));
+        }
+        if option.takes_file() {
+            line.push_str(" -rF");
+        } else if option.value.is_some() {
+            line.push_str(" -x");
+        }
+        line.push_str(&format!(" -d '{}'\n", escape(&option.help)));
+        line
+    };
+
+    let mut script = String::from("# fish completion for raffi\ncomplete -c raffi -f\n");
+    for option in options {
+        script.push_str(&line("__fish_use_subcommand", option));
+    }
+    for command in commands {
+        script.push_str(&format!(
+            "complete -c raffi -n __fish_use_subcommand -a {} -d '{}'\n",
+            command.name,
+            escape(&command.help)
+        ));
+        let condition = format!("__fish_seen_subcommand_from {}", command.name);
+        for option in &command.options {
+            script.push_str(&line(&condition, option));
+        }
+        match command_values(&command.name) {
+            Some(CommandValues::Shells) => script.push_str(&format!(
+                "complete -c raffi -n '{}' -a '{}'\n",
+                condition,
+                SHELLS.join(" ")
+            )),
+            Some(CommandValues::Entries) => script.push_str(&format!(
+                "complete -c raffi -n '{}' -a '(raffi completions --entries 2>/dev/null)'\n",
+                condition
+            )),
+            None => {}
+        }
+    }
+    script
+}
@@ -0,0 +1,193 @@
+//! Compositor IPC, to find the windows currently open or focused on sway,
+//! Hyprland and niri.
+
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+/// Run a compositor IPC command and parse its JSON output.
+fn query(program: &str, args: &[&str]) -> Result<Value> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .context(format!("cannot run {}", program))?;
+    if !output.status.success() {
+        anyhow::bail!("{} {} failed", program, args.join(" "));
+    }
+    serde_json::from_slice(&output.stdout).context(format!("cannot parse {} output", program))
+}
+
+/// Collect the pids of the windows in a sway tree.
+fn sway_pids(node: &Value, pids: &mut Vec<u32>) {
+    if let Some(pid) = node.get("pid").and_then(Value::as_u64) {
+        pids.push(pid as u32);
+    }
+    for key in ["nodes", "floating_nodes"] {
+        for child in node
+            .get(key)
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            sway_pids(child, pids);
+        }
+    }
+}
+
+/// Return the pids of the windows open on the running compositor.
+pub fn window_pids() -> Result<Vec<u32>> {
+    let mut pids = Vec::new();
+    if std::env::var_os("SWAYSOCK").is_some() {
+        sway_pids(&query("swaymsg", &["-r", "-t", "get_tree"])?, &mut pids);
+    } else {
+        let windows = if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+            query("hyprctl", &["clients", "-j"])?
+        } else if std::env::var_os("NIRI_SOCKET").is_some() {
+            query("niri", &["msg", "--json", "windows"])?
+        } else {
+            anyhow::bail!("no supported compositor found, raffi knows sway, Hyprland and niri");
+        };
+        pids.extend(
+            windows
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|window| window.get("pid").and_then(Value::as_u64))
+                .map(|pid| pid as u32),
+        );
+    }
+    Ok(pids)
+}
+
+/// An open window and the command focusing it.
+pub struct Window {
+    pub app: String,
+    pub title: String,
+    pub focus: Vec<String>,
+}
+
+/// Return a string field of a window, looking in the X11 properties too.
+fn window_field(window: &Value, key: &str) -> Option<String> {
+    window
+        .get(key)
+        .or_else(|| {
+            window
+                .get("window_properties")
+                .and_then(|props| props.get(key))
+        })
+        .and_then(Value::as_str)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+/// Collect the windows of a sway tree.
+fn sway_windows(node: &Value, windows: &mut Vec<Window>) {
+    if node.get("pid").is_some() {
+        if let Some(id) = node.get("id").and_then(Value::as_u64) {
+            windows.push(Window {
+                app: window_field(node, "app_id")
+                    .or_else(|| window_field(node, "class"))
+                    .unwrap_or_default(),
+                title: window_field(node, "name").unwrap_or_default(),
+                focus: vec![
+                    "swaymsg".to_string(),
+                    format!("[con_id={}]", id),
+                    "focus".to_string(),
+                ],
+            });
+        }
+    }
+    for key in ["nodes", "floating_nodes"] {
+        for child in node
+            .get(key)
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            sway_windows(child, windows);
+        }
+    }
+}
+
+/// Return the windows open on the running compositor.
+pub fn windows() -> Result<Vec<Window>> {
+    let mut windows = Vec::new();
+    if std::env::var_os("SWAYSOCK").is_some() {
+        sway_windows(&query("swaymsg", &["-r", "-t", "get_tree"])?, &mut windows);
+    } else if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        let clients = query("hyprctl", &["clients", "-j"])?;
+        for client in clients.as_array().into_iter().flatten() {
+            let Some(address) = window_field(client, "address") else {
+                continue;
+            };
+            // hidden special windows are listed too
+            if client.get("mapped").and_then(Value::as_bool) == Some(false) {
+                continue;
+            }
+            windows.push(Window {
+                app: window_field(client, "class").unwrap_or_default(),
+                title: window_field(client, "title").unwrap_or_default(),
+                focus: vec![
+                    "hyprctl".to_string(),
+                    "dispatch".to_string(),
+                    "focuswindow".to_string(),
+                    format!("address:{}", address),
+                ],
+            });
+        }
+    } else if std::env::var_os("NIRI_SOCKET").is_some() {
+        let niri_windows = query("niri", &["msg", "--json", "windows"])?;
+        for window in niri_windows.as_array().into_iter().flatten() {
+            let Some(id) = window.get("id").and_then(Value::as_u64) else {
+                continue;
+            };
+            windows.push(Window {
+                app: window_field(window, "app_id").unwrap_or_default(),
+                title: window_field(window, "title").unwrap_or_default(),
+                focus: vec![
+                    "niri".to_string(),
+                    "msg".to_string(),
+                    "action".to_string(),
+                    "focus-window".to_string(),
+                    "--id".to_string(),
+                    id.to_string(),
+                ],
+            });
+        }
+    } else {
+        anyhow::bail!("no supported compositor found, raffi knows sway, Hyprland and niri");
+    }
+    Ok(windows)
+}
+
+/// Find the focused window in a sway tree.
+fn sway_focused(node: &Value) -> Option<&Value> {
+    if node.get("focused").and_then(Value::as_bool) == Some(true) {
+        return Some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node.get(key).and_then(Value::as_array))
+        .flatten()
+        .find_map(sway_focused)
+}
+
+/// Return the app_id (or X11 class) of the focused window, None when it cannot
+/// be found.
+pub fn focused_app() -> Option<String> {
+    let (window, keys): (Value, &[&str]) = if std::env::var_os("SWAYSOCK").is_some() {
+        let tree = query("swaymsg", &["-r", "-t", "get_tree"]).ok()?;
+        (sway_focused(&tree)?.clone(), &["app_id", "class"])
+    } else if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        (query("hyprctl", &["activewindow", "-j"]).ok()?, &["class"])
+    } else if std::env::var_os("NIRI_SOCKET").is_some() {
+        (
+            query("niri", &["msg", "--json", "focused-window"]).ok()?,
+            &["app_id"],
+        )
+    } else {
+        return None;
+    };
+    keys.iter().find_map(|key| window_field(&window, key))
+}
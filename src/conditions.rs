@@ -0,0 +1,370 @@
+//! Conditions deciding if an entry is shown: flat `if*` fields that must all
+//! hold, combined with `allof`, `anyof` and `not` blocks of more conditions.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{compositor, expand_tilde, find_binary, parse_duration};
+
+/// Conditions of an entry, or of a block of conditions.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct Conditions {
+    /// Only show the entry if the environment variable equals a value: [VAR, value].
+    pub ifenveq: Option<Vec<String>>,
+    /// Only show the entry if the environment variable matches a regex: [VAR, regex].
+    pub ifenvmatches: Option<Vec<String>>,
+    /// Only show the entry if the environment variable is set.
+    pub ifenvset: Option<String>,
+    /// Only show the entry if the environment variable is not set.
+    pub ifenvnotset: Option<String>,
+    /// Only show the entry if the binary exists in the PATH.
+    pub ifexist: Option<String>,
+    /// Only show the entry in this desktop, matched against XDG_CURRENT_DESKTOP.
+    pub ifdesktop: Option<String>,
+    /// Only show the entry in this type of session: wayland, x11 or tty.
+    pub ifsessiontype: Option<String>,
+    /// Only show the entry on the machines matching this hostname or glob.
+    pub ifhostname: Option<String>,
+    /// Only show the entry if this shell command exits successfully.
+    pub ifcommand: Option<String>,
+    /// Time given to the ifcommand before it counts as failed (default: 1s).
+    pub ifcommand_timeout: Option<String>,
+    /// Only show the entry if the file was modified recently: [path, duration].
+    pub ifmodifiedwithin: Option<Vec<String>>,
+    /// Only show the entry if the compositor supports a Wayland protocol, e.g. wlr-layer-shell.
+    pub ifprotocol: Option<String>,
+    /// Only show the entry if the focused window has this app_id or class.
+    pub ifactiveapp: Option<String>,
+    /// Only show the entry if all of these blocks of conditions hold.
+    pub allof: Option<Vec<Conditions>>,
+    /// Only show the entry if one of these blocks of conditions holds.
+    pub anyof: Option<Vec<Conditions>>,
+    /// Only show the entry if this block of conditions doesn't hold.
+    pub not: Option<Box<Conditions>>,
+}
+
+impl Conditions {
+    /// Check if every condition holds, no condition at all always holds.
+    pub fn met(&self) -> bool {
+        self.failed().is_none()
+    }
+
+    /// Return the first condition that doesn't hold, None when they all do.
+    pub fn failed(&self) -> Option<String> {
+        if let Some(eq) = &self.ifenveq {
+            if eq.len() != 2 || std::env::var(&eq[0]).unwrap_or_default() != eq[1] {
+                return Some(format!("ifenveq: {} doesn't equal the value", eq.join(" ")));
+            }
+        }
+        if let Some(matches) = &self.ifenvmatches {
+            if matches.len() != 2 || !env_matches(&matches[0], &matches[1]) {
+                return Some(format!("ifenvmatches: {} doesn't match", matches.join(" ")));
+            }
+        }
+        if let Some(var) = &self.ifenvset {
+            if std::env::var(var).is_err() {
+                return Some(format!("ifenvset: {} is not set", var));
+            }
+        }
+        if let Some(var) = &self.ifenvnotset {
+            if std::env::var(var).is_ok() {
+                return Some(format!("ifenvnotset: {} is set", var));
+            }
+        }
+        if let Some(binary) = &self.ifexist {
+            // a full path only has to exist, it can be any file
+            let exists = if binary.contains('/') {
+                Path::new(&expand_tilde(binary)).exists()
+            } else {
+                find_binary(binary)
+            };
+            if !exists {
+                return Some(format!("ifexist: {} not found", binary));
+            }
+        }
+        if let Some(desktop) = &self.ifdesktop {
+            if !is_current_desktop(desktop) {
+                return Some(format!("ifdesktop: the desktop is not {}", desktop));
+            }
+        }
+        if let Some(session_type) = &self.ifsessiontype {
+            let current = current_session_type();
+            if !session_type.eq_ignore_ascii_case(&current) {
+                return Some(format!("ifsessiontype: the session is {}", current));
+            }
+        }
+        if let Some(pattern) = &self.ifhostname {
+            let hostname = hostname();
+            if !glob_match(&pattern.to_lowercase(), &hostname.to_lowercase()) {
+                return Some(format!(
+                    "ifhostname: {} doesn't match {}",
+                    hostname, pattern
+                ));
+            }
+        }
+        if let Some(command) = &self.ifcommand {
+            let timeout = self
+                .ifcommand_timeout
+                .as_deref()
+                .and_then(parse_duration)
+                .unwrap_or(IFCOMMAND_TIMEOUT);
+            if !command_succeeds(command, timeout) {
+                return Some(format!("ifcommand: {} failed", command));
+            }
+        }
+        if let Some(mw) = &self.ifmodifiedwithin {
+            if mw.len() != 2 || !modified_within(&mw[0], &mw[1]) {
+                return Some(format!(
+                    "ifmodifiedwithin: {} not modified within",
+                    mw.join(" ")
+                ));
+            }
+        }
+        if let Some(protocol) = &self.ifprotocol {
+            let Some(globals) = wayland_globals() else {
+                return Some("ifprotocol: wayland-info not found".to_string());
+            };
+            if !has_wayland_protocol(globals, protocol) {
+                return Some(format!("ifprotocol: {} not supported", protocol));
+            }
+        }
+        if let Some(app) = &self.ifactiveapp {
+            if !is_active_app(app) {
+                return Some(format!("ifactiveapp: {} is not focused", app));
+            }
+        }
+        if let Some(reason) = self.allof.iter().flatten().find_map(Conditions::failed) {
+            return Some(format!("allof: {}", reason));
+        }
+        if let Some(blocks) = &self.anyof {
+            if !blocks.iter().any(Conditions::met) {
+                return Some("anyof: none of the blocks holds".to_string());
+            }
+        }
+        if self.not.as_ref().is_some_and(|block| block.met()) {
+            return Some("not: the block holds".to_string());
+        }
+        None
+    }
+}
+
+/// Check if an environment variable matches a regex, an unset variable or an
+/// invalid regex never match.
+fn env_matches(var: &str, pattern: &str) -> bool {
+    let Ok(value) = std::env::var(var) else {
+        return false;
+    };
+    regex_lite::Regex::new(pattern).is_ok_and(|regex| regex.is_match(&value))
+}
+
+/// Check if a desktop is one of the colon separated XDG_CURRENT_DESKTOP names,
+/// ignoring case.
+fn is_current_desktop(desktop: &str) -> bool {
+    std::env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(':')
+        .any(|current| current.eq_ignore_ascii_case(desktop))
+}
+
+/// Return the type of the session: XDG_SESSION_TYPE when set, otherwise
+/// guessed from the display variables.
+fn current_session_type() -> String {
+    match std::env::var("XDG_SESSION_TYPE") {
+        Ok(session_type) if !session_type.is_empty() => session_type,
+        _ if std::env::var_os("WAYLAND_DISPLAY").is_some() => "wayland".to_string(),
+        _ if std::env::var_os("DISPLAY").is_some() => "x11".to_string(),
+        _ => "tty".to_string(),
+    }
+}
+
+/// Return the hostname of the machine.
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+/// Match a text against a glob pattern where `*` matches any characters and
+/// `?` a single one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and of the text it started matching at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, start)) => {
+                    p = star + 1;
+                    t = start + 1;
+                    backtrack = Some((star, start + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Time given to an ifcommand when the entry doesn't set one.
+const IFCOMMAND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Check if a shell command exits successfully within the timeout. Results
+/// are kept for the run, entries sharing a command run it once.
+fn command_succeeds(command: &str, timeout: std::time::Duration) -> bool {
+    static RESULTS: std::sync::Mutex<Option<HashMap<String, bool>>> = std::sync::Mutex::new(None);
+    let mut results = RESULTS.lock().unwrap_or_else(|err| err.into_inner());
+    let results = results.get_or_insert_with(HashMap::new);
+    if let Some(succeeded) = results.get(command) {
+        return *succeeded;
+    }
+
+    let succeeded = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .is_ok_and(|mut child| {
+            // a timeout too far away to be represented never expires
+            let deadline = std::time::Instant::now().checked_add(timeout);
+            loop {
+                match child.try_wait() {
+                    Ok(Some(status)) => return status.success(),
+                    Ok(None)
+                        if deadline.is_none_or(|deadline| std::time::Instant::now() < deadline) =>
+                    {
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                    }
+                    _ => {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return false;
+                    }
+                }
+            }
+        });
+    tracing::debug!("ifcommand {:?} succeeded: {}", command, succeeded);
+    results.insert(command.to_string(), succeeded);
+    succeeded
+}
+
+/// Check if a file was modified within the given duration.
+fn modified_within(path: &str, duration: &str) -> bool {
+    let path = expand_tilde(path);
+    let Some(duration) = parse_duration(duration) else {
+        return false;
+    };
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|elapsed| elapsed <= duration)
+}
+
+/// Return the interfaces of the globals advertised by the compositor, queried
+/// once with `wayland-info`, none when it cannot be run.
+fn wayland_globals() -> Option<&'static [String]> {
+    static GLOBALS: std::sync::OnceLock<Option<Vec<String>>> = std::sync::OnceLock::new();
+    GLOBALS
+        .get_or_init(|| {
+            let Ok(output) = Command::new("wayland-info").stderr(Stdio::null()).output() else {
+                eprintln!("wayland-info not found, entries with ifprotocol are hidden");
+                return None;
+            };
+            Some(
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .filter_map(|line| line.split("interface: '").nth(1))
+                    .filter_map(|rest| rest.split('\'').next())
+                    .map(str::to_string)
+                    .collect(),
+            )
+        })
+        .as_deref()
+}
+
+/// Return the protocol name part of an interface, without the unstable `z`
+/// and `wp_` prefixes nor the `_manager` and version suffixes:
+/// `zwp_idle_inhibit_manager_v1` is `idle_inhibit`.
+fn interface_protocol(interface: &str) -> &str {
+    let name = interface
+        .rsplit_once("_v")
+        .filter(|(_, version)| !version.is_empty() && version.chars().all(|c| c.is_ascii_digit()))
+        .map_or(interface, |(name, _)| name);
+    let name = name.strip_suffix("_manager").unwrap_or(name);
+    let name = name.strip_prefix('z').unwrap_or(name);
+    name.strip_prefix("wp_").unwrap_or(name)
+}
+
+/// Check if the compositor advertises a protocol, given by its exact interface
+/// name (`zwlr_layer_shell_v1`) or its protocol name (`wlr-layer-shell`).
+fn has_wayland_protocol(globals: &[String], protocol: &str) -> bool {
+    let protocol_name = protocol.replace('-', "_");
+    globals
+        .iter()
+        .any(|interface| interface == protocol || interface_protocol(interface) == protocol_name)
+}
+
+/// Check if the focused window belongs to an app, by app_id or X11 class,
+/// ignoring case. The focused window is asked once to the compositor.
+fn is_active_app(app: &str) -> bool {
+    static FOCUSED: std::sync::OnceLock<Option<String>> = std::sync::OnceLock::new();
+    FOCUSED
+        .get_or_init(compositor::focused_app)
+        .as_ref()
+        .is_some_and(|focused| focused.eq_ignore_ascii_case(app))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interface_protocol_strips_prefixes_and_suffixes() {
+        assert_eq!(
+            interface_protocol("zwp_idle_inhibit_manager_v1"),
+            "idle_inhibit"
+        );
+        assert_eq!(interface_protocol("zwlr_layer_shell_v1"), "wlr_layer_shell");
+        assert_eq!(interface_protocol("wp_viewporter"), "viewporter");
+        assert_eq!(interface_protocol("xdg_wm_base"), "xdg_wm_base");
+        assert_eq!(
+            interface_protocol("ext_foreign_toplevel_list_v1"),
+            "ext_foreign_toplevel_list"
+        );
+        assert_eq!(interface_protocol("wl_seat_vx"), "wl_seat_vx");
+    }
+
+    #[test]
+    fn has_wayland_protocol_by_interface_or_name() {
+        let globals = vec![
+            "wl_compositor".to_string(),
+            "zwlr_layer_shell_v1".to_string(),
+        ];
+        assert!(has_wayland_protocol(&globals, "zwlr_layer_shell_v1"));
+        assert!(has_wayland_protocol(&globals, "wlr-layer-shell"));
+        assert!(!has_wayland_protocol(&globals, "idle-inhibit"));
+    }
+}
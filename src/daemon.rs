@@ -0,0 +1,197 @@
+//! Resident mode: `raffi daemon` keeps the entries loaded, with their icons
+//! resolved, and hands them over a Unix socket to `raffi show` which then only
+//! has to check their conditions and run the launcher.
+
+use std::{
+    ffi::CString,
+    fs,
+    io::{Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    sync::OnceLock,
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{cache_dir, RaffiConfig};
+
+/// An entry as sent to the clients, with the fields the config doesn't carry.
+#[derive(Serialize, Deserialize)]
+struct DaemonEntry {
+    name: String,
+    icon_path: Option<String>,
+    config: RaffiConfig,
+}
+
+/// What the daemon sends to the clients.
+#[derive(Serialize, Deserialize)]
+struct Payload {
+    /// The configuration file the entries come from.
+    configfile: String,
+    entries: Vec<DaemonEntry>,
+}
+
+/// Return the path of the daemon socket.
+pub fn socket_path() -> String {
+    match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(runtime_dir) => format!("{}/raffi.sock", runtime_dir),
+        Err(_) => format!("{}/raffi.sock", cache_dir()),
+    }
+}
+
+/// Serialize the entries sent to the clients.
+fn make_payload(configfile: &str, rafficonfigs: Vec<RaffiConfig>) -> Result<Vec<u8>> {
+    let entries: Vec<DaemonEntry> = rafficonfigs
+        .into_iter()
+        .map(|mut config| DaemonEntry {
+            name: std::mem::take(&mut config.name),
+            icon_path: config.icon_path.take(),
+            config,
+        })
+        .collect();
+    let payload = Payload {
+        configfile: canonical(configfile),
+        entries,
+    };
+    serde_json::to_vec(&payload).context("Failed to serialize entries")
+}
+
+/// Return the absolute path of a configuration file, to compare the one of
+/// the daemon with the one of the client.
+fn canonical(configfile: &str) -> String {
+    fs::canonicalize(configfile)
+        .ok()
+        .and_then(|path| path.to_str().map(str::to_string))
+        .unwrap_or_else(|| configfile.to_string())
+}
+
+/// The socket removed when the daemon is stopped.
+static SOCKET: OnceLock<CString> = OnceLock::new();
+
+extern "C" fn remove_socket_and_exit(signal: libc::c_int) {
+    // only async-signal-safe calls in a signal handler
+    if let Some(path) = SOCKET.get() {
+        unsafe { libc::unlink(path.as_ptr()) };
+    }
+    unsafe { libc::_exit(128 + signal) };
+}
+
+/// Remove the socket when the daemon is stopped by a signal, so that a stale
+/// one is not left behind.
+fn remove_socket_on_exit(path: &str) -> Result<()> {
+    let path = CString::new(path).context("invalid socket path")?;
+    let _ = SOCKET.set(path);
+    let handler = remove_socket_and_exit as extern "C" fn(libc::c_int);
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+    }
+    Ok(())
+}
+
+fn modified(configfile: &str) -> Option<SystemTime> {
+    fs::metadata(configfile)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Serve the entries loaded by `load` to every client connecting to the
+/// socket, loading them again when the configuration file has changed. The
+/// ones of `generate` (sessions, plugins, ssh hosts) come from files the
+/// daemon doesn't watch and are built again for every client.
+pub fn serve(
+    configfile: &str,
+    load: impl Fn() -> Result<Vec<RaffiConfig>>,
+    generate: impl Fn() -> Result<Vec<RaffiConfig>>,
+) -> Result<()> {
+    let path = socket_path();
+    if UnixStream::connect(&path).is_ok() {
+        anyhow::bail!("a raffi daemon is already listening on {}", path);
+    }
+    // a socket left over by a daemon that didn't exit cleanly
+    let _ = fs::remove_file(&path);
+    if let Some(parent) = Path::new(&path).parent() {
+        fs::create_dir_all(parent).context("Failed to create socket directory")?;
+    }
+    let listener = UnixListener::bind(&path).context(format!("cannot listen on {}", path))?;
+    remove_socket_on_exit(&path)?;
+
+    let mut loaded = modified(configfile);
+    let (mut entries, mut generated) = match load().and_then(|entries| Ok((entries, generate()?))) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            let _ = fs::remove_file(&path);
+            return Err(err);
+        }
+    };
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut stream) => {
+                let current = modified(configfile);
+                if current != loaded {
+                    loaded = current;
+                    // keep the previous entries while the file is half edited
+                    match load() {
+                        Ok(reloaded) => entries = reloaded,
+                        Err(err) => {
+                            eprintln!("raffi daemon: cannot reload {}: {:#}", configfile, err)
+                        }
+                    }
+                }
+                match generate() {
+                    Ok(regenerated) => generated = regenerated,
+                    Err(err) => {
+                        eprintln!("raffi daemon: cannot read the generated entries: {:#}", err)
+                    }
+                }
+                let all = entries.iter().chain(&generated).cloned().collect();
+                match make_payload(configfile, all) {
+                    // a client going away early is not the daemon's problem
+                    Ok(payload) => {
+                        let _ = stream.write_all(&payload);
+                    }
+                    Err(err) => eprintln!("raffi daemon: {:#}", err),
+                }
+            }
+            Err(err) => eprintln!("raffi daemon: cannot accept connection: {}", err),
+        }
+    }
+    let _ = fs::remove_file(&path);
+    Ok(())
+}
+
+/// Fetch the entries of the configuration file from a running daemon, None
+/// when there is none. The daemon serving another configuration file is an
+/// error rather than a menu of the wrong entries.
+pub fn fetch_entries(configfile: &str) -> Result<Option<Vec<RaffiConfig>>> {
+    let Ok(mut stream) = UnixStream::connect(socket_path()) else {
+        return Ok(None);
+    };
+    let mut payload = Vec::new();
+    if stream.read_to_end(&mut payload).is_err() {
+        return Ok(None);
+    }
+    let Ok(payload) = serde_json::from_slice::<Payload>(&payload) else {
+        return Ok(None);
+    };
+    if payload.configfile != canonical(configfile) {
+        anyhow::bail!(
+            "the raffi daemon serves {}, not {}, restart it with this configuration file",
+            payload.configfile,
+            configfile
+        );
+    }
+    Ok(Some(
+        payload
+            .entries
+            .into_iter()
+            .map(|entry| RaffiConfig {
+                name: entry.name,
+                icon_path: entry.icon_path,
+                ..entry.config
+            })
+            .collect(),
+    ))
+}
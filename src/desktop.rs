@@ -0,0 +1,112 @@
+//! Desktop files: the `Icon=` of the installed applications, for binaries
+//! whose icon isn't named after them, e.g. `google-chrome-stable`.
+
+use std::{collections::HashMap, fs, path::Path};
+
+/// Return the directories the desktop files are looked up in, the ones of the
+/// user first.
+fn applications_dirs() -> Vec<String> {
+    let data_home = crate::data_home();
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    std::iter::once(data_home.as_str())
+        .chain(data_dirs.split(':'))
+        .map(|dir| format!("{}/applications", dir))
+        .chain(
+            crate::flatpak_dirs()
+                .iter()
+                .map(|dir| format!("{}/exports/share/applications", dir)),
+        )
+        .collect()
+}
+
+/// Return the binary name and the icon of a desktop file, from the `Exec=`
+/// and `Icon=` keys of its `[Desktop Entry]` group.
+fn parse(contents: &str) -> Option<(String, String)> {
+    let (mut exec, mut icon) = (None, None);
+    let mut in_entry = false;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+        } else if in_entry {
+            match line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+            {
+                Some(("Exec", value)) => exec = Some(value),
+                Some(("Icon", value)) if !value.is_empty() => icon = Some(value),
+                _ => {}
+            }
+        }
+    }
+    // skip `env VAR=value` prefixes to get to the program
+    let program = exec?
+        .split_whitespace()
+        .find(|word| *word != "env" && !word.contains('='))?
+        .trim_matches('"');
+    let binary = Path::new(program).file_name()?.to_str()?;
+    Some((binary.to_string(), icon?.to_string()))
+}
+
+/// Return the icons of the installed applications by binary name, the first
+/// desktop file found for a binary winning.
+pub fn icons() -> HashMap<String, String> {
+    let mut icons = HashMap::new();
+    for dir in applications_dirs() {
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "desktop"))
+            .collect();
+        files.sort();
+        for (binary, icon) in files
+            .iter()
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .filter_map(|contents| parse(&contents))
+        {
+            icons.entry(binary).or_insert(icon);
+        }
+    }
+    tracing::debug!(
+        "{} application icons read from the desktop files",
+        icons.len()
+    );
+    icons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_exec_and_icon() {
+        let contents =
+            "[Desktop Entry]\nName=Firefox\nExec=/usr/lib/firefox/firefox %u\nIcon=firefox\n";
+        assert_eq!(
+            parse(contents),
+            Some(("firefox".to_string(), "firefox".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_skips_env_and_other_groups() {
+        let contents =
+            "[Desktop Entry]\nExec=env GDK_BACKEND=x11 \"/opt/app/bin/app\" %F\nIcon = app\n\
+                        [Desktop Action new]\nExec=other\nIcon=other\n";
+        assert_eq!(
+            parse(contents),
+            Some(("app".to_string(), "app".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_needs_exec_and_icon() {
+        assert_eq!(parse("[Desktop Entry]\nExec=app\nIcon=\n"), None);
+        assert_eq!(parse("[Desktop Entry]\nIcon=app\n"), None);
+        assert_eq!(parse("[Other]\nExec=app\nIcon=app\n"), None);
+    }
+}
@@ -0,0 +1,39 @@
+//! Emoji picker: every Unicode emoji as an entry copying it to the clipboard.
+
+use anyhow::Result;
+
+use crate::RaffiConfig;
+
+/// Return an entry for every emoji, copying it with wl-copy on Wayland and
+/// xclip otherwise.
+#[cfg(feature = "emoji")]
+pub fn entries() -> Result<Vec<RaffiConfig>> {
+    let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    Ok(emojis::iter()
+        .map(|emoji| {
+            let glyph = emoji.as_str().to_string();
+            let mut mc = RaffiConfig {
+                description: Some(format!("{} {}", glyph, emoji.name())),
+                name: emoji.shortcode().unwrap_or(emoji.name()).to_string(),
+                ..Default::default()
+            };
+            if wayland {
+                mc.binary = Some("wl-copy".to_string());
+                mc.args = Some(vec!["--".to_string(), glyph]);
+            } else {
+                mc.binary = Some("sh".to_string());
+                mc.script = Some(format!(
+                    "printf %s {} | xclip -selection clipboard",
+                    crate::shell_quote(&glyph)
+                ));
+            }
+            mc
+        })
+        .collect())
+}
+
+/// The emoji list is compiled out.
+#[cfg(not(feature = "emoji"))]
+pub fn entries() -> Result<Vec<RaffiConfig>> {
+    anyhow::bail!("raffi was built without the emoji feature")
+}
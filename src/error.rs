@@ -0,0 +1,78 @@
+//! Failures callers may want to tell apart, carried through anyhow and
+//! recovered with `downcast_ref` where the kind matters.
+
+use thiserror::Error;
+
+/// The kinds of failure of raffi.
+#[derive(Debug, Error)]
+pub enum RaffiError {
+    /// The menu was closed without choosing anything.
+    #[error("cancelled")]
+    Cancelled,
+    /// The configuration file could not be opened.
+    #[error("cannot open config file {file}")]
+    ConfigRead {
+        file: String,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The configuration file isn't valid YAML or doesn't match the expected
+    /// format.
+    #[error("cannot parse config file {file}")]
+    ConfigSyntax {
+        file: String,
+        #[source]
+        source: serde_yaml::Error,
+    },
+    /// An entry of the configuration file doesn't match the expected format.
+    #[error(
+        "cannot parse config entry {entry}{}",
+        .line.map(|line| format!(" at line {}", line)).unwrap_or_default()
+    )]
+    ConfigParse {
+        entry: String,
+        line: Option<usize>,
+        #[source]
+        source: serde_yaml::Error,
+    },
+    /// The configuration file is valid YAML but not a configuration raffi can
+    /// use, e.g. a version from the future or an unknown profile.
+    #[error("{0}")]
+    ConfigInvalid(String),
+    /// The menu program could not be started.
+    #[error("cannot launch {backend}")]
+    UiFailed {
+        backend: String,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The menu program failed rather than being cancelled.
+    #[error("{backend} failed with {status}: {stderr}")]
+    UiExited {
+        backend: String,
+        status: std::process::ExitStatus,
+        stderr: String,
+    },
+    /// The command of an entry could not be started.
+    #[error("cannot launch {command}")]
+    SpawnFailed {
+        command: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl RaffiError {
+    /// Return the exit code raffi terminates with on this failure.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            RaffiError::Cancelled => 130,
+            RaffiError::ConfigRead { .. }
+            | RaffiError::ConfigSyntax { .. }
+            | RaffiError::ConfigParse { .. }
+            | RaffiError::ConfigInvalid(_) => 2,
+            RaffiError::UiFailed { .. } | RaffiError::UiExited { .. } => 3,
+            RaffiError::SpawnFailed { .. } => 4,
+        }
+    }
+}
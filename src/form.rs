@@ -0,0 +1,231 @@
+//! Form entries: named fields asked one after the other with fuzzel before
+//! the entry runs, their values substituted as `{name}` in its args and given
+//! to its script in `RAFFI_FORM_<NAME>` environment variables.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::run_fuzzel;
+
+/// A field of a form entry.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct FormField {
+    /// Name of the field, substituted as `{name}` in the args and given to the
+    /// script in `RAFFI_FORM_<NAME>`.
+    pub name: String,
+    /// Kind of input, defaults to text.
+    #[serde(default, rename = "type")]
+    pub kind: FieldKind,
+    /// Prompt shown in fuzzel, defaults to the name.
+    pub prompt: Option<String>,
+    /// Values offered by a choice field.
+    pub choices: Option<Vec<String>>,
+}
+
+/// Kind of input of a form field.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    /// Free text.
+    #[default]
+    Text,
+    /// One of the choices.
+    Choice,
+    /// Free text, hidden while typing.
+    Password,
+}
+
+/// A value asked hidden before running an entry and given to its command in
+/// an environment variable, so it doesn't show in the process arguments.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+pub struct SecretPrompt {
+    /// Environment variable the value is passed in.
+    pub env: String,
+    /// Prompt shown in fuzzel, defaults to the variable name.
+    pub prompt: Option<String>,
+}
+
+/// Ask a secret, None when it was cancelled.
+pub fn ask_secret(secret: &SecretPrompt, user_args: &[String]) -> Result<Option<String>> {
+    let field = FormField {
+        name: secret.env.clone(),
+        kind: FieldKind::Password,
+        prompt: secret.prompt.clone(),
+        choices: None,
+    };
+    Ok(ask(&[field], user_args)?.and_then(|mut values| values.remove(&secret.env)))
+}
+
+/// Ask every field in order, None when one of them was cancelled. The user's
+/// fuzzel arguments come first so the prompt and the password mode are kept.
+pub fn ask(fields: &[FormField], user_args: &[String]) -> Result<Option<HashMap<String, String>>> {
+    let mut values = HashMap::new();
+    for field in fields {
+        let prompt = format!(
+            "--prompt={}: ",
+            field.prompt.as_deref().unwrap_or(&field.name)
+        );
+        let mut fuzzel_args: Vec<&str> = user_args.iter().map(String::as_str).collect();
+        fuzzel_args.push(&prompt);
+        let output = match field.kind {
+            FieldKind::Text => {
+                fuzzel_args.push("--lines=0");
+                run_fuzzel("", &fuzzel_args)?
+            }
+            FieldKind::Password => {
+                fuzzel_args.extend(["--lines=0", "--password"]);
+                run_fuzzel("", &fuzzel_args)?
+            }
+            FieldKind::Choice => {
+                let choices: String = field
+                    .choices
+                    .iter()
+                    .flatten()
+                    .map(|choice| format!("{}\n", choice))
+                    .collect();
+                run_fuzzel(&choices, &fuzzel_args)?
+            }
+        };
+        let Some(value) = output else {
+            return Ok(None);
+        };
+        values.insert(field.name.clone(), value.trim_end_matches('\n').to_string());
+    }
+    Ok(Some(values))
+}
+
+/// Start and end of the placeholders asked when the entry runs.
+const PROMPT_OPEN: &str = "{{prompt:";
+const PROMPT_CLOSE: &str = "}}";
+
+/// Replace the `<open>name<close>` placeholders of a text that `value` gives
+/// a value for, in a single pass so a value is never replaced again. With
+/// `escapable`, a placeholder preceded by a backslash is kept as is, without
+/// the backslash.
+fn replace_placeholders(
+    text: &str,
+    open: &str,
+    close: &str,
+    escapable: bool,
+    mut value: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(open) {
+        let after = &rest[start + open.len()..];
+        let Some(end) = after.find(close) else {
+            break;
+        };
+        let placeholder = &rest[start..start + open.len() + end + close.len()];
+        if escapable && rest[..start].ends_with('\\') {
+            replaced.push_str(&rest[..start - 1]);
+            replaced.push_str(placeholder);
+        } else if let Some(value) = value(&after[..end]) {
+            replaced.push_str(&rest[..start]);
+            replaced.push_str(&value);
+        } else {
+            replaced.push_str(&rest[..start + open.len()]);
+            rest = after;
+            continue;
+        }
+        rest = &rest[start + placeholder.len()..];
+    }
+    replaced.push_str(rest);
+    replaced
+}
+
+/// Return a text field for every `{{prompt:name}}` placeholder of the texts,
+/// once per name.
+pub fn placeholders<'a>(texts: impl IntoIterator<Item = &'a str>) -> Vec<FormField> {
+    let mut fields: Vec<FormField> = Vec::new();
+    for text in texts {
+        replace_placeholders(text, PROMPT_OPEN, PROMPT_CLOSE, true, |name| {
+            if !fields.iter().any(|field| field.name == name) {
+                fields.push(FormField {
+                    name: name.to_string(),
+                    kind: FieldKind::Text,
+                    prompt: None,
+                    choices: None,
+                });
+            }
+            None
+        });
+    }
+    fields
+}
+
+/// Replace the `{{prompt:name}}` placeholders with the values typed for them.
+pub fn fill_placeholders(text: &str, values: &HashMap<String, String>) -> String {
+    replace_placeholders(text, PROMPT_OPEN, PROMPT_CLOSE, true, |name| {
+        values.get(name).cloned()
+    })
+}
+
+/// Replace the `{name}` placeholders of the fields with their values.
+pub fn substitute(text: &str, values: &HashMap<String, String>) -> String {
+    replace_placeholders(text, "{", "}", false, |name| values.get(name).cloned())
+}
+
+/// Return the environment variable the value of a field is given in,
+/// `RAFFI_FORM_<NAME>` with the name upper cased.
+pub fn env_name(field: &str) -> String {
+    let name: String = field
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("RAFFI_FORM_{}", name)
+}
+
+/// Fail when a field is used as `{name}` in a script, where its value would
+/// become code. `${name}` is a shell variable and is left alone.
+pub fn check_script(fields: &[FormField], script: &str) -> Result<()> {
+    for field in fields {
+        let placeholder = format!("{{{}}}", field.name);
+        if script
+            .match_indices(&placeholder)
+            .any(|(start, _)| !script[..start].ends_with('$'))
+        {
+            anyhow::bail!(
+                "field {} can't be used in the script, read the {} environment variable instead",
+                field.name,
+                env_name(&field.name)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Fail when a password field is used in args, where it would show in the
+/// process list.
+pub fn check_passwords<'a>(
+    fields: &[FormField],
+    args: impl IntoIterator<Item = &'a str>,
+) -> Result<()> {
+    let args: Vec<&str> = args.into_iter().collect();
+    for field in fields
+        .iter()
+        .filter(|field| matches!(field.kind, FieldKind::Password))
+    {
+        let placeholder = format!("{{{}}}", field.name);
+        if args.iter().any(|arg| arg.contains(&placeholder)) {
+            anyhow::bail!(
+                "password field {} can't be used in args, they show in the process list, use it in the script or use prompt_secret",
+                field.name
+            );
+        }
+    }
+    Ok(())
+}
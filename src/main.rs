@@ -1,37 +1,343 @@
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
     fs::{self, File},
-    io::{Read, Write},
-    os::unix::fs::PermissionsExt,
+    io::Write,
+    os::unix::{fs::PermissionsExt, process::CommandExt},
     path::Path,
-    process::{Command, Stdio},
+    process::{Command, ExitCode, Stdio},
+    sync::OnceLock,
 };
 
+mod bench;
+mod clipboard;
+mod completions;
+mod compositor;
+mod conditions;
+mod daemon;
+mod desktop;
+mod emoji;
+mod error;
+mod form;
+mod plugin;
+mod session;
+mod ssh;
+
 use anyhow::{Context, Result};
 use gumdrop::Options;
-use serde::Deserialize;
+use indexmap::IndexMap;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
+
+use error::RaffiError;
 
 /// Represents the configuration for each Raffi entry.
-#[derive(Deserialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 struct RaffiConfig {
+    /// Binary to run, or interpreter of the script.
     binary: Option<String>,
+    /// Arguments passed to the binary.
     args: Option<Vec<String>>,
+    /// Icon name or path, defaults to the binary name.
     icon: Option<String>,
+    /// Text shown in the launcher.
     description: Option<String>,
-    ifenveq: Option<Vec<String>>,
-    ifenvset: Option<String>,
-    ifenvnotset: Option<String>,
-    ifexist: Option<String>,
+    /// Section of the menu the entry is shown in.
+    group: Option<String>,
+    /// Words the entry is also found by, without being shown.
+    keywords: Option<Vec<String>>,
+    /// Conditions to show the entry.
+    #[serde(flatten)]
+    conditions: conditions::Conditions,
+    /// Hide the entry.
     disabled: Option<bool>,
+    /// Script to run with the interpreter.
     script: Option<String>,
+    /// Language of the script, choosing its interpreter when there is no
+    /// binary.
+    script_lang: Option<ScriptLang>,
+    /// Path of an executable or script to run, with the binary as interpreter
+    /// when set.
+    script_file: Option<String>,
+    /// Flatpak application ID, run with `flatpak run`.
+    flatpak: Option<String>,
+    /// URL opened in its own app-mode browser window.
+    webapp: Option<String>,
+    /// Browser used for the webapp, defaults to the first installed one.
+    browser: Option<String>,
+    /// Value passed to the track_command setting when the entry is launched.
+    track: Option<String>,
+    /// Launch the entry in its own systemd user scope, overrides the setting.
+    systemd_scope: Option<bool>,
+    /// Run the entry as a systemd user service restarted by this policy.
+    restart: Option<RestartPolicy>,
+    /// Maximum number of restarts of the service (default: 3).
+    max_restarts: Option<u32>,
+    /// Commands run one after the other instead of a single binary.
+    commands: Option<Vec<CommandStep>>,
+    /// Stop running the commands at the first one that fails.
+    stop_on_failure: Option<bool>,
+    /// Run the entry after a delay such as `5s`, cancellable from a notification.
+    delay: Option<String>,
+    /// Ask for confirmation before running the entry, true or the question.
+    confirm: Option<Confirm>,
+    /// Show a notification when the entry is launched, overriding the
+    /// `notify` setting.
+    notify: Option<bool>,
+    /// Command wrapping the entry, e.g. `[firejail]`, or false to not use the
+    /// `sandbox` setting.
+    sandbox: Option<Sandbox>,
+    /// Fields asked before running the entry, substituted as `{name}` in its
+    /// args and script.
+    form: Option<Vec<form::FormField>>,
+    /// Value asked hidden before running the entry, passed in an environment
+    /// variable of its command rather than in its args.
+    prompt_secret: Option<form::SecretPrompt>,
+    /// Directory the entry runs in.
+    cwd: Option<String>,
+    /// Name of the entry, its key in the configuration file.
+    #[serde(skip)]
+    name: String,
+    /// Resolved icon path, set when the launcher shows icons.
+    #[serde(skip)]
+    icon_path: Option<String>,
+    /// Environment variables given to the command only, like the secret.
+    #[serde(skip)]
+    env: Vec<(String, String)>,
+}
+
+/// When systemd restarts an entry launched as a service.
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+enum RestartPolicy {
+    No,
+    OnFailure,
+    Always,
+}
+
+/// Languages of the scripts with a known interpreter.
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "lowercase")]
+enum ScriptLang {
+    Python,
+    Node,
+    Fish,
+    Nu,
+}
+
+impl ScriptLang {
+    /// Return the binary running the scripts of the language.
+    fn interpreter(self) -> &'static str {
+        match self {
+            ScriptLang::Python => "python3",
+            ScriptLang::Node => "node",
+            ScriptLang::Fish => "fish",
+            ScriptLang::Nu => "nu",
+        }
+    }
+}
+
+/// How the entries of the menu are ordered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+enum SortMode {
+    /// The order of the configuration file.
+    #[default]
+    Config,
+    /// Alphabetical order of the descriptions.
+    Alpha,
+    /// How often the entries are chosen, kept by fuzzel.
+    Mru,
+    /// The launch history and the time of day, like `--suggest`.
+    Frecency,
+}
+
+impl std::str::FromStr for SortMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "config" => Ok(SortMode::Config),
+            "alpha" => Ok(SortMode::Alpha),
+            "mru" => Ok(SortMode::Mru),
+            "frecency" => Ok(SortMode::Frecency),
+            _ => Err(format!(
+                "unknown sort mode {}, use one of: config, alpha, mru, frecency",
+                mode
+            )),
+        }
+    }
+}
+
+/// A command of a multi-command entry.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+struct CommandStep {
+    /// Binary to run.
+    binary: String,
+    /// Arguments passed to the binary.
+    args: Option<Vec<String>>,
+}
+
+/// Confirmation asked before running an entry.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(untagged)]
+enum Confirm {
+    Enabled(bool),
+    Message(String),
 }
 
-/// Represents the top-level configuration structure.
+/// Sandbox wrapping the command of an entry.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(untagged)]
+enum Sandbox {
+    Enabled(bool),
+    Wrapper(Vec<String>),
+}
+
+/// Default maximum number of restarts of a service entry.
+const DEFAULT_MAX_RESTARTS: u32 = 3;
+
+/// Current version of the configuration file format.
+const CONFIG_VERSION: u64 = 2;
+
+/// Represents the top-level configuration structure, once migrated to the
+/// current version.
 #[derive(Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 struct Config {
-    #[serde(flatten)]
-    toplevel: HashMap<String, Value>,
+    /// Version of the configuration format.
+    #[allow(dead_code)] // checked by migrate_config, kept for the schema
+    version: u64,
+    /// Launcher entries by name.
+    #[serde(default)]
+    #[cfg_attr(feature = "schema", schemars(with = "HashMap<String, RaffiConfig>"))]
+    entries: IndexMap<String, Value>,
+    /// Global settings.
+    #[serde(default)]
+    settings: Settings,
+    /// Named subsets of the entries, chosen with `--profile`.
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+impl Config {
+    /// Return the entries of a profile with its overrides applied, or all the
+    /// entries without a profile.
+    fn profile_entries(&self, profile: Option<&str>) -> Result<IndexMap<String, Value>> {
+        let Some(name) = profile else {
+            return Ok(self.entries.clone());
+        };
+        let profile = self.profiles.get(name).ok_or_else(|| {
+            let mut names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            names.sort();
+            RaffiError::ConfigInvalid(if names.is_empty() {
+                format!("no profile named {}, the config file has no profiles", name)
+            } else {
+                format!(
+                    "no profile named {}, use one of: {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+        })?;
+        Ok(self
+            .entries
+            .iter()
+            .filter(|(entry, _)| profile.includes(entry))
+            .map(|(entry, value)| {
+                let mut value = value.clone();
+                if let (Some(mapping), Some(overrides)) =
+                    (value.as_mapping_mut(), profile.overrides.get(entry))
+                {
+                    for (field, field_value) in overrides {
+                        mapping.insert(field.clone(), field_value.clone());
+                    }
+                }
+                (entry.clone(), value)
+            })
+            .collect())
+    }
+
+    /// Whether an entry that is not in the `entries` section, such as a
+    /// session or a plugin, is part of the profile.
+    fn profile_includes(&self, profile: Option<&str>, entry: &str) -> bool {
+        profile
+            .and_then(|name| self.profiles.get(name))
+            .is_none_or(|profile| profile.includes(entry))
+    }
+}
+
+/// A subset of the entries, from the `profiles` section of the configuration
+/// file.
+#[derive(Default, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+struct Profile {
+    /// Names or glob patterns of the entries shown, all of them when empty.
+    entries: Vec<String>,
+    /// Fields replacing those of the entries, by entry name.
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "HashMap<String, HashMap<String, serde_json::Value>>")
+    )]
+    overrides: HashMap<String, serde_yaml::Mapping>,
+}
+
+impl Profile {
+    /// Whether the entry matches one of the names or patterns of the profile.
+    fn includes(&self, entry: &str) -> bool {
+        self.entries.is_empty()
+            || self
+                .entries
+                .iter()
+                .any(|pattern| conditions::glob_match(pattern, entry))
+    }
+}
+
+/// Global settings, from the `settings` section of the configuration file.
+#[derive(Default, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(default)]
+struct Settings {
+    /// Command run when an entry with `track` is launched, with the track value
+    /// as last argument, e.g. `timew start`.
+    track_command: Option<String>,
+    /// Launch the entries in their own systemd user scope.
+    systemd_scope: bool,
+    /// Extra arguments given to fuzzel for the menu and the prompts, e.g.
+    /// `--lines=20`.
+    fuzzel_args: Vec<String>,
+    /// Prompt shown by fuzzel in front of the input.
+    prompt: Option<String>,
+    /// Text shown by fuzzel in the empty input.
+    placeholder: Option<String>,
+    /// Launch the entry as soon as the filter narrows down to it.
+    auto_select: bool,
+    /// Glob patterns of the ssh hosts to show "SSH to <host>" entries for.
+    ssh_hosts: Vec<String>,
+    /// Run the typed text with the default script shell when it matches no
+    /// entry.
+    allow_custom_command: bool,
+    /// How the entries of the menu are ordered.
+    sort: SortMode,
+    /// Show a notification when an entry is launched.
+    notify: bool,
+    /// Command wrapping every entry, e.g. `[firejail, --private]`.
+    sandbox: Vec<String>,
+    /// Refuse entries with unknown fields instead of warning about them.
+    strict: bool,
+    /// Commands used by the clipboard subcommand.
+    clipboard: clipboard::ClipboardSettings,
 }
 
 /// Command-line arguments structure.
@@ -39,12 +345,19 @@ struct Config {
 struct Args {
     #[options(help = "print help message")]
     help: bool,
-    #[options(help = "print version")]
+    #[options(help = "print version", short = "V")]
     version: bool,
+    #[options(
+        count,
+        help = "log what raffi does on stderr, -vv for details, or use RAFFI_LOG"
+    )]
+    verbose: u32,
     #[options(help = "config file location")]
     configfile: Option<String>,
     #[options(help = "print command to stdout, do not run it")]
     print_only: bool,
+    #[options(help = "print the chosen entry as JSON, do not run it", no_short)]
+    print_json: bool,
     #[options(help = "refresh cache")]
     refresh_cache: bool,
     #[options(help = "do not show icons", short = "I")]
@@ -55,16 +368,174 @@ struct Args {
         short = "P"
     )]
     default_script_shell: String,
+    #[options(
+        help = "split the menu in alphabetical pages when it has more entries than this",
+        meta = "N"
+    )]
+    page_size: Option<usize>,
+    #[options(
+        help = "order the menu from the launch history and time of day",
+        short = "s"
+    )]
+    suggest: bool,
+    #[options(
+        help = "order of the menu: config, alpha, mru or frecency",
+        meta = "MODE",
+        no_short
+    )]
+    sort: Option<SortMode>,
+    #[options(help = "wait for the command and report when it fails")]
+    wait: bool,
+    #[options(help = "print the skipped entries and why on stderr", no_short)]
+    show_skipped: bool,
+    #[options(
+        help = "extra argument given to fuzzel for the menu and the prompts, can be repeated",
+        meta = "ARG",
+        no_short
+    )]
+    fuzzel_arg: Vec<String>,
+    #[options(
+        help = "read the menu items from stdin, as label[TAB icon[TAB command]]",
+        no_short
+    )]
+    stdin: bool,
+    #[options(
+        help = "launch the entry as soon as it is the only one matching",
+        no_short
+    )]
+    auto_select: bool,
+    #[options(
+        help = "highlight the entry with this name or glob, or else start the search with it",
+        meta = "TEXT",
+        no_short
+    )]
+    select: Option<String>,
+    #[options(help = "only show the entries of this group", meta = "NAME", no_short)]
+    group: Option<String>,
+    #[options(help = "show the entries of this profile", meta = "NAME", no_short)]
+    profile: Option<String>,
+    #[options(command)]
+    command: Option<Subcommand>,
+}
+
+/// Subcommands, running raffi without one shows the launcher.
+#[derive(Debug, Options)]
+enum Subcommand {
+    #[options(help = "show the launcher (default)")]
+    Launch(HelpOnlyArgs),
+    #[options(help = "list the entries shown in the launcher")]
+    List(ListArgs),
+    #[options(help = "run an entry directly, without showing the launcher")]
+    Run(RunArgs),
+    #[options(help = "check the configuration file")]
+    Validate(HelpOnlyArgs),
+    #[options(help = "time the stages before the launcher shows up")]
+    Bench(BenchArgs),
+    #[options(help = "keep the entries and icons loaded for raffi show")]
+    Daemon(HelpOnlyArgs),
+    #[options(help = "show the launcher with the entries loaded by raffi daemon")]
+    Show(HelpOnlyArgs),
+    #[options(help = "manage the saved sessions: save NAME, list or remove NAME")]
+    Session(SessionArgs),
+    #[options(help = "show the clipboard history and copy the chosen entry back")]
+    Clipboard(HelpOnlyArgs),
+    #[options(help = "show the emoji and copy the chosen one to the clipboard")]
+    Emoji(HelpOnlyArgs),
+    #[options(help = "show the open windows and focus the chosen one")]
+    Windows(HelpOnlyArgs),
+    #[options(help = "show the menu of a plugin from the plugins directory")]
+    Plugin(PluginArgs),
+    #[options(help = "manage the caches: clear, rebuild or path")]
+    Cache(CacheArgs),
+    #[options(help = "rewrite the configuration file in the current format version")]
+    Migrate(HelpOnlyArgs),
+    #[options(help = "print the JSON schema of the configuration file")]
+    Schema(HelpOnlyArgs),
+    #[options(help = "print the shell completion script for bash, zsh or fish")]
+    Completions(CompletionsArgs),
+}
+
+/// Arguments of the completions subcommand.
+#[derive(Debug, Options)]
+struct CompletionsArgs {
+    #[options(help = "print help message")]
+    help: bool,
+    #[options(help = "print the entry names, used by the completion scripts")]
+    entries: bool,
+    #[options(free, help = "shell to generate the completions for")]
+    shell: Option<String>,
+}
+
+/// Arguments of the list subcommand.
+#[derive(Debug, Options)]
+struct ListArgs {
+    #[options(help = "print help message")]
+    help: bool,
+    #[options(help = "print the entries as JSON")]
+    json: bool,
+}
+
+/// Arguments of the run subcommand.
+#[derive(Debug, Options)]
+struct RunArgs {
+    #[options(help = "print help message")]
+    help: bool,
+    #[options(free, help = "name of the entry to run")]
+    entry: Option<String>,
+}
+
+/// Arguments of the bench subcommand.
+#[derive(Debug, Options)]
+struct BenchArgs {
+    #[options(help = "print help message")]
+    help: bool,
+    #[options(help = "number of runs of each stage (default: 10)", meta = "N")]
+    runs: Option<usize>,
+}
+
+/// Arguments of the session subcommand.
+#[derive(Debug, Options)]
+struct SessionArgs {
+    #[options(help = "print help message")]
+    help: bool,
+    #[options(free, help = "action: save NAME, list or remove NAME")]
+    action: Vec<String>,
+}
+
+/// Arguments of the plugin subcommand.
+#[derive(Debug, Options)]
+struct PluginArgs {
+    #[options(help = "print help message")]
+    help: bool,
+    #[options(free, help = "name of the plugin")]
+    name: Option<String>,
+}
+
+/// Arguments of the cache subcommand.
+#[derive(Debug, Options)]
+struct CacheArgs {
+    #[options(help = "print help message")]
+    help: bool,
+    #[options(free, help = "action: clear, rebuild or path")]
+    action: Option<String>,
+}
+
+/// Arguments of subcommands that don't take any option.
+#[derive(Debug, Options)]
+struct HelpOnlyArgs {
+    #[options(help = "print help message")]
+    help: bool,
 }
 
-/// Get the icon mapping from system directories.
-fn get_icon_map() -> Result<HashMap<String, String>> {
-    let mut icon_map = HashMap::new();
-    let iconhome = std::env::var("XDG_DATA_HOME")
-        .unwrap_or_else(|_| format!("{}/.local/share", std::env::var("HOME").unwrap_or_default()))
-        + "/icons";
+/// Get the icon mapping from system directories, limited to the wanted icon names.
+#[cfg(feature = "icons")]
+fn get_icon_map(wanted: &HashSet<String>) -> Result<HashMap<String, String>> {
+    let mut icon_map: HashMap<String, String> = HashMap::new();
+    let iconhome = format!("{}/icons", data_home());
 
-    let icon_dirs = vec!["/usr/share/icons", "/usr/share/pixmaps", &iconhome];
+    let flatpak_icons = flatpak_dirs().map(|dir| format!("{}/exports/share/icons", dir));
+    let mut icon_dirs = vec!["/usr/share/icons", "/usr/share/pixmaps", &iconhome];
+    icon_dirs.extend(flatpak_icons.iter().map(String::as_str));
 
     for dir in icon_dirs {
         for entry in walkdir::WalkDir::new(dir)
@@ -72,12 +543,26 @@ fn get_icon_map() -> Result<HashMap<String, String>> {
             .filter_map(Result::ok)
         {
             let fname = entry.file_name().to_string_lossy().to_string();
+            // only the extension is dropped, flatpak icons are named like org.gimp.GIMP
+            let name = fname
+                .rsplit_once('.')
+                .map_or(fname.as_str(), |(name, _)| name);
+            if !wanted.contains(name) {
+                continue;
+            }
             if let Some(ext) = entry.path().extension().and_then(|s| s.to_str()) {
                 if ext == "png" || ext == "svg" {
-                    icon_map.insert(
-                        fname.split('.').next().unwrap().to_string(),
-                        entry.path().to_string_lossy().to_string(),
-                    );
+                    let path = entry.path().to_string_lossy().to_string();
+                    // symbolic icons are monochrome glyphs hardly visible in
+                    // the menu, any other variant is better
+                    if is_symbolic_icon(&path)
+                        && icon_map
+                            .get(name)
+                            .is_some_and(|known| !is_symbolic_icon(known))
+                    {
+                        continue;
+                    }
+                    icon_map.insert(name.to_string(), path);
                 }
             }
         }
@@ -85,80 +570,594 @@ fn get_icon_map() -> Result<HashMap<String, String>> {
     Ok(icon_map)
 }
 
-/// Read the configuration file and return a list of RaffiConfig.
-fn read_config(filename: &str, args: &Args) -> Result<Vec<RaffiConfig>> {
-    let file = File::open(filename).context(format!("cannot open config file {}", filename))?;
-    let config: Config =
-        serde_yaml::from_reader(file).context(format!("cannot parse config file {}", filename))?;
+/// Whether an icon file is a symbolic variant, from a `symbolic` directory or
+/// named `*-symbolic`.
+#[cfg(feature = "icons")]
+fn is_symbolic_icon(path: &str) -> bool {
+    path.contains("/symbolic/") || path.contains("-symbolic.")
+}
+
+/// Icon scanning is compiled out, no icon can be resolved.
+#[cfg(not(feature = "icons"))]
+fn get_icon_map(_wanted: &HashSet<String>) -> Result<HashMap<String, String>> {
+    Ok(HashMap::new())
+}
+
+/// Migrate a configuration document to the current format version.
+///
+/// Version 1 is the original layout without a `version` key, where every
+/// top-level mapping is an entry. Version 2 moves entries under `entries:` to
+/// leave room for non-entry sections.
+fn migrate_config(value: Value) -> Result<Value> {
+    let version = value.get("version").and_then(Value::as_u64).unwrap_or(1);
+    match version {
+        1 => {
+            let mut entries = serde_yaml::Mapping::new();
+            if let Value::Mapping(toplevel) = value {
+                for (name, entry) in toplevel {
+                    if entry.is_mapping() {
+                        entries.insert(name, entry);
+                    }
+                }
+            }
+            let mut migrated = serde_yaml::Mapping::new();
+            migrated.insert("version".into(), CONFIG_VERSION.into());
+            migrated.insert("entries".into(), Value::Mapping(entries));
+            Ok(Value::Mapping(migrated))
+        }
+        CONFIG_VERSION => Ok(value),
+        _ => Err(RaffiError::ConfigInvalid(format!(
+            "unsupported config version {}, this raffi supports up to version {}",
+            version, CONFIG_VERSION
+        ))
+        .into()),
+    }
+}
+
+/// Log to stderr at the level given by RAFFI_LOG, e.g. `debug` or
+/// `raffi=trace`, or else by the number of `-v`, warnings only by default.
+fn init_logging(verbose: u32) {
+    let level = match verbose {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    };
+    let filter = std::env::var("RAFFI_LOG")
+        .ok()
+        .and_then(|directives| directives.parse::<Targets>().ok())
+        .unwrap_or_else(|| level.parse().unwrap_or_default());
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::IsTerminal::is_terminal(&std::io::stderr()))
+        .without_time()
+        .finish()
+        .with(filter)
+        .init();
+}
+
+/// Return the places the configuration file is looked up in, in order.
+fn config_candidates() -> Vec<String> {
+    let dir = format!("{}/raffi", config_home());
+    ["raffi.yaml", "raffi.yml", "config.yaml"]
+        .iter()
+        .map(|name| format!("{}/{}", dir, name))
+        .collect()
+}
+
+/// Return the first configuration file found, the usual one when there is
+/// none so the error names it.
+fn find_config() -> String {
+    let candidates = config_candidates();
+    candidates
+        .iter()
+        .find(|path| Path::new(path).exists())
+        .unwrap_or(&candidates[0])
+        .clone()
+}
+
+/// Read the configuration file and migrate it to the current version.
+fn load_config(filename: &str) -> Result<Config> {
+    let file = File::open(filename).map_err(|source| {
+        let err = anyhow::Error::new(RaffiError::ConfigRead {
+            file: filename.to_string(),
+            source,
+        });
+        let candidates = config_candidates();
+        if candidates.iter().any(|path| path == filename) {
+            err.context(format!(
+                "no config file found, looked for {} (or set RAFFI_CONFIG or use --configfile)",
+                candidates.join(", ")
+            ))
+        } else {
+            err
+        }
+    })?;
+    let syntax_error = |source| RaffiError::ConfigSyntax {
+        file: filename.to_string(),
+        source,
+    };
+    let value: Value = serde_yaml::from_reader(file).map_err(syntax_error)?;
+    let config: Config = serde_yaml::from_value(migrate_config(value)?).map_err(syntax_error)?;
+    tracing::debug!("loaded {} with {} entries", filename, config.entries.len());
+    Ok(config)
+}
+
+/// Return the line number of an entry in the configuration file, to point
+/// at it in errors. Only the keys at the level of the entries match, those of
+/// the `entries:` section or the top level ones in version 1 files.
+fn entry_line(filename: &str, name: &str) -> Option<usize> {
+    let contents = fs::read_to_string(filename).ok()?;
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let is_content = |line: &str| !line.trim().is_empty() && !line.trim_start().starts_with('#');
+    let lines: Vec<&str> = contents.lines().collect();
+    let (start, end, level) = match lines.iter().position(|line| line.trim_end() == "entries:") {
+        Some(section) => {
+            let end = lines[section + 1..]
+                .iter()
+                .position(|line| is_content(line) && indent(line) == 0)
+                .map_or(lines.len(), |offset| section + 1 + offset);
+            let level = lines[section + 1..end]
+                .iter()
+                .find(|line| is_content(line))
+                .map_or(0, |line| indent(line));
+            (section + 1, end, level)
+        }
+        None => (0, lines.len(), 0),
+    };
+    let keys = [
+        name.to_string(),
+        format!("\"{}\"", name),
+        format!("'{}'", name),
+    ];
+    (start..end)
+        .find(|&index| {
+            let line = lines[index];
+            indent(line) == level
+                && keys.iter().any(|key| {
+                    line.trim_start()
+                        .strip_prefix(key.as_str())
+                        .is_some_and(|rest| rest.trim_start().starts_with(':'))
+                })
+        })
+        .map(|index| index + 1)
+}
+
+/// Rewrite the configuration file in the current format version, keeping a
+/// backup of the original next to it.
+fn migrate_config_file(filename: &str) -> Result<()> {
+    let contents =
+        fs::read_to_string(filename).context(format!("cannot read config file {}", filename))?;
+    let value: Value = serde_yaml::from_str(&contents)
+        .context(format!("cannot parse config file {}", filename))?;
+    if value.get("version").and_then(Value::as_u64) == Some(CONFIG_VERSION) {
+        println!("{} is already at version {}", filename, CONFIG_VERSION);
+        return Ok(());
+    }
+    let migrated = serde_yaml::to_string(&migrate_config(value)?)
+        .context("Failed to serialize migrated config")?;
+    let backup = format!("{}.bak", filename);
+    fs::write(&backup, contents).context(format!("cannot write backup {}", backup))?;
+    fs::write(filename, migrated).context(format!("cannot write config file {}", filename))?;
+    println!(
+        "{} migrated to version {}, the original is saved as {} (comments are not preserved)",
+        filename, CONFIG_VERSION, backup
+    );
+    Ok(())
+}
+
+/// Return the fields of an entry that raffi doesn't know, usually typos.
+fn unknown_fields(value: &Value) -> Vec<String> {
+    static KNOWN: OnceLock<HashSet<String>> = OnceLock::new();
+    // every field is serialized, as null when it's not set
+    let known = KNOWN.get_or_init(|| match serde_json::to_value(RaffiConfig::default()) {
+        Ok(serde_json::Value::Object(fields)) => fields.into_iter().map(|(key, _)| key).collect(),
+        _ => HashSet::new(),
+    });
+    value
+        .as_mapping()
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, _)| key.as_str())
+        .filter(|key| !known.contains(*key))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Warn about the unknown fields of an entry, or fail with the `strict`
+/// setting.
+fn check_fields(name: &str, value: &Value, settings: &Settings) -> Result<()> {
+    let unknown = unknown_fields(value);
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    if settings.strict {
+        return Err(RaffiError::ConfigInvalid(format!(
+            "{}: unknown fields {}",
+            name,
+            unknown.join(", ")
+        ))
+        .into());
+    }
+    for field in unknown {
+        eprintln!("{}: unknown field {}, ignored", name, field);
+    }
+    Ok(())
+}
+
+/// Read the configuration file and return a list of RaffiConfig, without the
+/// entries whose conditions fail when they are checked.
+fn read_config(filename: &str, args: &Args, check_conditions: bool) -> Result<Vec<RaffiConfig>> {
+    let config = load_config(filename)?;
+    forget_found_binaries();
     let mut rafficonfigs = Vec::new();
 
-    for value in config.toplevel.values() {
+    for (name, value) in &config.profile_entries(args.profile.as_deref())? {
         if value.is_mapping() {
-            let mut mc: RaffiConfig = serde_yaml::from_value(value.clone())
-                .context("cannot parse config entry".to_string())?;
-            if mc.disabled.unwrap_or(false) || !is_valid_config(&mut mc, args) {
+            check_fields(name, value, &config.settings)?;
+            let mut mc: RaffiConfig = match serde_yaml::from_value(value.clone()) {
+                Ok(mc) => mc,
+                Err(source) => {
+                    let err = RaffiError::ConfigParse {
+                        entry: name.clone(),
+                        line: entry_line(filename, name),
+                        source,
+                    };
+                    if config.settings.strict {
+                        return Err(err.into());
+                    }
+                    // one broken entry should not take the whole menu down
+                    eprintln!("{:#}, skipped", anyhow::Error::from(err));
+                    continue;
+                }
+            };
+            mc.name = name.clone();
+            expand_entry(&mut mc);
+            if let Some(reason) = skip_reason(&mut mc, args, check_conditions) {
+                if args.show_skipped {
+                    eprintln!("{}: skipped, {}", name, reason);
+                }
+                tracing::debug!("{}: skipped, {}", name, reason);
                 continue;
             }
             rafficonfigs.push(mc);
         }
     }
+    tracing::info!("{} entries shown from {}", rafficonfigs.len(), filename);
     Ok(rafficonfigs)
 }
 
-/// Validate the RaffiConfig based on various conditions.
-fn is_valid_config(mc: &mut RaffiConfig, args: &Args) -> bool {
-    if let Some(_script) = &mc.script {
-        if !find_binary(mc.binary.as_deref().unwrap_or(&args.default_script_shell)) {
-            return false;
+/// Expand `~` and environment variables in the binary, icon and cwd, and `~`
+/// and `${VAR}` in the args only: args are often code like `sh -c 'echo $1'`.
+fn expand_entry(mc: &mut RaffiConfig) {
+    for field in mc
+        .binary
+        .iter_mut()
+        .chain(mc.icon.iter_mut())
+        .chain(mc.cwd.iter_mut())
+    {
+        *field = expand_vars(&expand_tilde(field), false);
+    }
+    for arg in mc.args.iter_mut().flatten() {
+        *arg = expand_vars(&expand_tilde(arg), true);
+    }
+}
+
+/// Expand `$VAR` and `${VAR}`, or only `${VAR}` with `braced_only`, unset
+/// variables are kept as they are.
+fn expand_vars(text: &str, braced_only: bool) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        let after = &rest[idx + 1..];
+        let (var, remaining) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.split_once('}') {
+                Some((var, remaining)) => (var, remaining),
+                None => ("", after),
+            }
+        } else if braced_only {
+            ("", after)
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            after.split_at(end)
+        };
+        match std::env::var(var) {
+            Ok(value) if !var.is_empty() => {
+                expanded.push_str(&value);
+                rest = remaining;
+            }
+            // keep what isn't an environment variable, like shell variables
+            // in `sh -c` args
+            _ => {
+                expanded.push('$');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Validate the RaffiConfig based on various conditions, returning why it is
+/// not shown. Its `if*` conditions are only checked with `check_conditions`.
+fn skip_reason(mc: &mut RaffiConfig, args: &Args, check_conditions: bool) -> Option<String> {
+    if mc.disabled.unwrap_or(false) {
+        return Some("disabled".to_string());
+    }
+    if let Some(url) = mc.webapp.clone() {
+        let Some(browser) = mc.browser.clone().or_else(|| {
+            WEBAPP_BROWSERS
+                .iter()
+                .find(|browser| find_binary(browser))
+                .map(|browser| browser.to_string())
+        }) else {
+            return Some("no browser found for the webapp".to_string());
+        };
+        if !find_binary(&browser) {
+            return Some(format!("browser {} not found", browser));
+        }
+        mc.args = Some(webapp_args(&browser, &url, &mc.name));
+        mc.binary = Some(browser);
+        if mc.description.is_none() {
+            mc.description = Some(mc.name.clone());
+        }
+    } else if let Some(app_id) = mc.flatpak.clone() {
+        if !find_binary("flatpak") {
+            return Some("flatpak not found".to_string());
+        }
+        if !flatpak_installed(&app_id) {
+            return Some(format!("flatpak {} not installed", app_id));
+        }
+        let mut flatpak_args = vec!["run".to_string(), app_id.clone()];
+        flatpak_args.extend(mc.args.take().unwrap_or_default());
+        mc.args = Some(flatpak_args);
+        mc.binary = Some("flatpak".to_string());
+        mc.icon.get_or_insert(app_id);
+        if mc.description.is_none() {
+            mc.description = Some(mc.name.clone());
+        }
+    } else if let Some(commands) = &mc.commands {
+        if let Some(step) = commands.iter().find(|step| !find_binary(&step.binary)) {
+            return Some(format!("binary {} not found", step.binary));
+        }
+        if mc.description.is_none() {
+            mc.description = Some(mc.name.clone());
+        }
+    } else if let Some(script_file) = mc.script_file.as_deref().map(expand_tilde) {
+        if !Path::new(&script_file).exists() {
+            return Some(format!("script file {} not found", script_file));
+        }
+        match &mc.binary {
+            Some(binary) => {
+                if !find_binary(binary) {
+                    return Some(format!("binary {} not found", binary));
+                }
+                let mut script_args = vec![script_file];
+                script_args.extend(mc.args.take().unwrap_or_default());
+                mc.args = Some(script_args);
+            }
+            None => mc.binary = Some(script_file),
+        }
+        if mc.description.is_none() {
+            mc.description = Some(mc.name.clone());
+        }
+    } else if let Some(_script) = &mc.script {
+        // the temporary script is gone by the time systemd restarts it
+        if mc.restart.is_some() {
+            return Some("restart can't be used with a script, use script_file".to_string());
+        }
+        if mc.binary.is_none() {
+            mc.binary = mc.script_lang.map(|lang| lang.interpreter().to_string());
+        }
+        let interpreter = mc.binary.as_deref().unwrap_or(&args.default_script_shell);
+        if !find_binary(interpreter) {
+            return Some(format!("interpreter {} not found", interpreter));
         }
     } else if let Some(binary) = &mc.binary {
         if !find_binary(binary) {
-            return false;
+            return Some(format!("binary {} not found", binary));
         }
     } else if let Some(description) = &mc.description {
         mc.binary = Some(description.clone());
     } else {
-        return false;
+        return Some("no binary, script or description".to_string());
     }
 
-    mc.ifenveq.as_ref().map_or(true, |eq| {
-        eq.len() == 2 && std::env::var(&eq[0]).unwrap_or_default() == eq[1]
-    }) && mc
-        .ifenvset
-        .as_ref()
-        .map_or(true, |var| std::env::var(var).is_ok())
-        && mc
-            .ifenvnotset
-            .as_ref()
-            .map_or(true, |var| std::env::var(var).is_err())
-        && mc.ifexist.as_ref().map_or(true, |exist| find_binary(exist))
+    if check_conditions {
+        mc.conditions.failed()
+    } else {
+        None
+    }
+}
+
+/// Return the flatpak installations, system wide and for the user.
+fn flatpak_dirs() -> [String; 2] {
+    [
+        "/var/lib/flatpak".to_string(),
+        format!("{}/flatpak", data_home()),
+    ]
+}
+
+/// Check if a flatpak application is installed, looking at the installations
+/// directly as `flatpak info` is too slow to run for every entry.
+fn flatpak_installed(app_id: &str) -> bool {
+    flatpak_dirs()
+        .iter()
+        .any(|dir| Path::new(&format!("{}/app/{}/current", dir, app_id)).exists())
+}
+
+/// Browsers tried in order for webapps without an explicit browser.
+const WEBAPP_BROWSERS: &[&str] = &["chromium", "google-chrome-stable", "brave", "firefox"];
+
+/// Return the dedicated browser profile directory of a webapp.
+fn webapp_profile_dir(name: &str) -> String {
+    format!("{}/raffi/webapps/{}", data_home(), name)
+}
+
+/// Build the browser arguments opening the URL as an app window with its own
+/// profile, and a stable `raffi-<name>` app_id/class for compositor rules.
+fn webapp_args(browser: &str, url: &str, name: &str) -> Vec<String> {
+    let app_id = format!("raffi-{}", name);
+    let profile = webapp_profile_dir(name);
+    if browser.contains("firefox") {
+        vec![
+            "--new-instance".to_string(),
+            "--profile".to_string(),
+            profile,
+            "--name".to_string(),
+            app_id,
+            "--kiosk".to_string(),
+            url.to_string(),
+        ]
+    } else {
+        vec![
+            format!("--app={}", url),
+            format!("--class={}", app_id),
+            format!("--user-data-dir={}", profile),
+        ]
+    }
+}
+
+/// Parse a duration such as `500ms`, `30s`, `15m`, `2h`, `1d` or `1w` (bare
+/// numbers are seconds).
+fn parse_duration(duration: &str) -> Option<std::time::Duration> {
+    let duration = duration.trim();
+    let (number, unit) = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or((duration, ""), |idx| duration.split_at(idx));
+    let millis = match unit.trim() {
+        "ms" => 1,
+        "" | "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        "w" => 7 * 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(millis))
+        .map(std::time::Duration::from_millis)
+}
+
+/// Expand a leading `~` or `~/` to the home directory.
+fn expand_tilde(path: &str) -> String {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{}", std::env::var("HOME").unwrap_or_default(), rest)
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// Results of find_binary, entries often share binaries.
+static FOUND_BINARIES: std::sync::Mutex<Option<HashMap<String, bool>>> =
+    std::sync::Mutex::new(None);
+
+/// Forget the binaries found, for the programs installed or removed since,
+/// e.g. when the daemon reloads.
+fn forget_found_binaries() {
+    *FOUND_BINARIES.lock().unwrap_or_else(|err| err.into_inner()) = None;
 }
 
-/// Check if a binary exists in the PATH.
+/// Check if a binary is an executable in the PATH, or at its path when it has
+/// one. Results are kept until the next configuration load.
 fn find_binary(binary: &str) -> bool {
-    std::env::var("PATH")
-        .unwrap_or_default()
-        .split(':')
-        .any(|path| Path::new(&format!("{}/{}", path, binary)).exists())
+    let mut found = FOUND_BINARIES.lock().unwrap_or_else(|err| err.into_inner());
+    let found = found.get_or_insert_with(HashMap::new);
+    if let Some(exists) = found.get(binary) {
+        return *exists;
+    }
+
+    let exists = if binary.contains('/') {
+        is_executable(Path::new(binary))
+    } else {
+        std::env::var_os("PATH").is_some_and(|path| {
+            std::env::split_paths(&path).any(|dir| is_executable(&dir.join(binary)))
+        })
+    };
+    found.insert(binary.to_string(), exists);
+    exists
 }
 
-/// Run the fuzzel command with the provided input and return its output.
-fn run_fuzzel_with_input(input: &str) -> Result<String> {
-    let cache_file = format!(
-        "{}/.cache/raffi/mru.cache",
-        std::env::var("XDG_CACHE_HOME")
-            .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_default().to_string())
-    );
+/// Whether the path is a file with an execute permission.
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// What was chosen in the fuzzel menu.
+enum Selection {
+    /// The index of the chosen line.
+    Line(usize),
+    /// Text typed by the user matching no line.
+    Text(String),
+}
+
+/// Run the fuzzel command with the provided input and return the chosen line,
+/// or the typed text when it matches none and `allow_text` is set, None when
+/// nothing was chosen.
+/// The MRU cache is only used for the entries menu, not for intermediate menus.
+/// The user fuzzel arguments come last, to override ours.
+fn run_fuzzel_with_input(
+    input: &str,
+    use_cache: bool,
+    allow_text: bool,
+    user_args: &[String],
+) -> Result<Option<Selection>> {
+    let cache_file = format!("{}/mru.cache", cache_dir());
     if let Some(parent) = Path::new(&cache_file).parent() {
         fs::create_dir_all(parent).context("Failed to create cache directory for fuzzel")?;
     }
+    let mut fuzzel_args = vec!["--counter"];
+    // fuzzel prints -1 instead of the typed text with --index, so the line
+    // is looked up from the text when the typed text is wanted
+    if !allow_text {
+        fuzzel_args.push("--index");
+    }
+    if use_cache {
+        fuzzel_args.extend(["--cache", &cache_file]);
+    }
+    fuzzel_args.extend(user_args.iter().map(String::as_str));
+    let Some(output) = run_fuzzel(input, &fuzzel_args)? else {
+        return Ok(None);
+    };
+    let output = output.trim_end_matches('\n');
+    if !allow_text {
+        tracing::debug!("fuzzel chose line {}", output.trim());
+        return Ok(output.trim().parse().ok().map(Selection::Line));
+    }
+    if output.trim().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        match input
+            .lines()
+            .position(|line| line.split('\0').next() == Some(output))
+        {
+            Some(index) => Selection::Line(index),
+            None => Selection::Text(output.to_string()),
+        },
+    ))
+}
+
+/// Run fuzzel in dmenu mode with extra arguments, None when it was cancelled.
+fn run_fuzzel(input: &str, fuzzel_args: &[&str]) -> Result<Option<String>> {
+    tracing::debug!("running fuzzel -d {}", fuzzel_args.join(" "));
     let mut child = Command::new("fuzzel")
-        .args(["-d", "--counter", "--cache", &cache_file])
+        .arg("-d")
+        .args(fuzzel_args)
         .stdout(Stdio::piped())
         .stdin(Stdio::piped())
-        .stderr(Stdio::null())
+        .stderr(Stdio::piped())
         .spawn()
-        .context("cannot launch fuzzel command")?;
+        .map_err(|source| RaffiError::UiFailed {
+            backend: "fuzzel".to_string(),
+            source,
+        })?;
 
     if let Some(stdin) = child.stdin.as_mut() {
         stdin
@@ -167,120 +1166,574 @@ fn run_fuzzel_with_input(input: &str) -> Result<String> {
     }
 
     let output = child.wait_with_output().context("failed to read output")?;
-    String::from_utf8(output.stdout).context("Invalid UTF-8 in output")
+    if !output.status.success() {
+        // fuzzel exits with 1 when cancelled, and logs its own errors as err:
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let errors: Vec<&str> = stderr
+            .lines()
+            .filter(|line| line.starts_with("err:"))
+            .collect();
+        if output.status.code() == Some(1) && errors.is_empty() {
+            tracing::info!("fuzzel exited with {}, nothing chosen", output.status);
+            return Ok(None);
+        }
+        let stderr = if errors.is_empty() {
+            stderr.trim().to_string()
+        } else {
+            errors.join("\n")
+        };
+        return Err(RaffiError::UiExited {
+            backend: "fuzzel".to_string(),
+            status: output.status,
+            stderr,
+        }
+        .into());
+    }
+    // what was typed is not logged, it can be a password or a form value
+    String::from_utf8(output.stdout)
+        .map(Some)
+        .context("Invalid UTF-8 in output")
+}
+
+/// Magic header and format version of the binary icon cache.
+const ICON_CACHE_MAGIC: &[u8; 4] = b"RAFI";
+const ICON_CACHE_VERSION: u32 = 1;
+
+/// Encode the icon map as a length-prefixed binary blob.
+fn encode_icon_cache(map: &HashMap<String, String>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16 + map.len() * 64);
+    buf.extend_from_slice(ICON_CACHE_MAGIC);
+    buf.extend_from_slice(&ICON_CACHE_VERSION.to_le_bytes());
+    buf.extend_from_slice(&(map.len() as u32).to_le_bytes());
+    for (name, path) in map {
+        for field in [name, path] {
+            buf.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            buf.extend_from_slice(field.as_bytes());
+        }
+    }
+    buf
+}
+
+/// Decode a binary icon cache, failing on unknown magic, version or truncated data.
+fn decode_icon_cache(data: &[u8]) -> Result<HashMap<String, String>> {
+    fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        if data.len() < len {
+            anyhow::bail!("truncated icon cache");
+        }
+        let (head, tail) = data.split_at(len);
+        *data = tail;
+        Ok(head)
+    }
+    fn take_u32(data: &mut &[u8]) -> Result<u32> {
+        Ok(u32::from_le_bytes(take(data, 4)?.try_into()?))
+    }
+    fn take_str(data: &mut &[u8]) -> Result<String> {
+        let len = take_u32(data)? as usize;
+        Ok(std::str::from_utf8(take(data, len)?)?.to_string())
+    }
+
+    let mut data = data;
+    if take(&mut data, ICON_CACHE_MAGIC.len())? != ICON_CACHE_MAGIC {
+        anyhow::bail!("not a raffi icon cache");
+    }
+    let version = take_u32(&mut data)?;
+    if version != ICON_CACHE_VERSION {
+        anyhow::bail!("unsupported icon cache version {}", version);
+    }
+    let count = take_u32(&mut data)? as usize;
+    // the count comes from the file, every entry takes at least its two
+    // lengths so a bogus one can't make a huge allocation
+    let mut map = HashMap::with_capacity(count.min(data.len() / 8));
+    for _ in 0..count {
+        let name = take_str(&mut data)?;
+        let path = take_str(&mut data)?;
+        map.insert(name, path);
+    }
+    Ok(map)
 }
 
 /// Save the icon map to a cache file.
 fn save_to_cache_file(map: &HashMap<String, String>) -> Result<()> {
-    let cache_dir = format!(
-        "{}/.cache/raffi",
-        std::env::var("XDG_CACHE_HOME")
-            .unwrap_or_else(|_| format!("{}/.cache", std::env::var("HOME").unwrap_or_default()))
-    );
+    fs::create_dir_all(cache_dir()).context("Failed to create cache directory")?;
 
-    fs::create_dir_all(&cache_dir).context("Failed to create cache directory")?;
-
-    let cache_file_path = format!("{}/icon.cache", cache_dir);
-    let mut cache_file = File::create(&cache_file_path).context("Failed to create cache file")?;
+    // written aside then renamed, so a concurrent raffi never reads half of it
+    let mut cache_file =
+        tempfile::NamedTempFile::new_in(cache_dir()).context("Failed to create cache file")?;
     cache_file
-        .write_all(
-            serde_json::to_string(map)
-                .context("Failed to serialize icon map")?
-                .as_bytes(),
-        )
+        .write_all(&encode_icon_cache(map))
         .context("Failed to write to cache file")?;
+    cache_file
+        .persist(icon_cache_path())
+        .context("Failed to save cache file")?;
     Ok(())
 }
 
-/// Read the icon map from the cache file or generate it if it doesn't exist.
-fn read_icon_map() -> Result<HashMap<String, String>> {
-    let cache_path = format!(
-        "{}/.cache/raffi/icon.cache",
-        std::env::var("XDG_CACHE_HOME")
-            .unwrap_or_else(|_| format!("{}/.cache", std::env::var("HOME").unwrap_or_default()))
-    );
+/// Return an XDG base directory, `fallback` in the home directory when the
+/// variable is not set or empty.
+fn xdg_home(var: &str, fallback: &str) -> String {
+    std::env::var(var)
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .unwrap_or_else(|| format!("{}/{}", std::env::var("HOME").unwrap_or_default(), fallback))
+}
 
-    if !Path::new(&cache_path).exists() {
-        let icon_map = get_icon_map()?;
-        save_to_cache_file(&icon_map)?;
-        return Ok(icon_map);
-    }
+/// Return the user data directory, `~/.local/share` by default.
+fn data_home() -> String {
+    xdg_home("XDG_DATA_HOME", ".local/share")
+}
 
-    let mut cache_file = File::open(&cache_path).context("Failed to open cache file")?;
-    let mut contents = String::new();
-    cache_file
-        .read_to_string(&mut contents)
-        .context("Failed to read cache file")?;
-    serde_json::from_str(&contents).context("Failed to deserialize cache file")
+/// Return the user configuration directory, `~/.config` by default.
+fn config_home() -> String {
+    xdg_home("XDG_CONFIG_HOME", ".config")
 }
 
-/// Create the input for fuzzel based on the Raffi configurations.
-fn make_fuzzel_input(rafficonfigs: &[RaffiConfig], no_icons: bool) -> Result<String> {
-    let icon_map = if no_icons {
-        HashMap::new()
-    } else {
-        read_icon_map()?
-    };
-    let mut ret = String::new();
+/// Return the user state directory, `~/.local/state` by default.
+fn state_home() -> String {
+    xdg_home("XDG_STATE_HOME", ".local/state")
+}
 
-    for mc in rafficonfigs {
-        let description = mc
-            .description
-            .clone()
-            .unwrap_or_else(|| mc.binary.clone().unwrap_or_else(|| "unknown".to_string()));
-        if no_icons {
-            ret.push_str(&format!("{}\n", description));
-        } else {
-            let icon = mc
-                .icon
-                .clone()
-                .unwrap_or_else(|| mc.binary.clone().unwrap_or_else(|| "unknown".to_string()));
-            let icon_path = icon_map
-                .get(&icon)
-                .unwrap_or(&"default".to_string())
-                .to_string();
-            ret.push_str(&format!("{}\0icon\x1f{}\n", description, icon_path));
-        }
-    }
-    Ok(ret)
+/// Return raffi's cache directory.
+fn cache_dir() -> String {
+    format!("{}/raffi", xdg_home("XDG_CACHE_HOME", ".cache"))
 }
 
-/// Execute the chosen command or script.
-fn execute_chosen_command(mc: &RaffiConfig, args: &Args, interpreter: &str) -> Result<()> {
-    // make interepreter with mc.binary and mc.args on the same line
-    let interpreter_with_args = mc.args.as_ref().map_or(interpreter.to_string(), |args| {
-        format!("{} {}", interpreter, args.join(" "))
+/// Return the path of the icon cache file.
+fn icon_cache_path() -> String {
+    format!("{}/icon.cache", cache_dir())
+}
+
+/// Resolve the wanted icon names, from the cache file when it knows all of
+/// them or by scanning the icon directories otherwise. Names that could not
+/// be found are cached with an empty path so they don't trigger a new scan.
+fn read_icon_map(wanted: &HashSet<String>) -> Result<HashMap<String, String>> {
+    let cache_path = icon_cache_path();
+
+    let mut cached = HashMap::new();
+    if Path::new(&cache_path).exists() {
+        let contents = fs::read(&cache_path).context("Failed to read cache file")?;
+        if let Ok(icon_map) = decode_icon_cache(&contents) {
+            if wanted.iter().all(|name| icon_map.contains_key(name)) {
+                tracing::debug!("icons read from the cache {}", cache_path);
+                return Ok(icon_map);
+            }
+            tracing::info!("icon cache misses some icons, scanning the icon directories");
+            cached = icon_map;
+        }
+    }
+
+    // binaries without an icon of their name get the one of their desktop file
+    let desktop_icons = desktop::icons();
+    let desktop_icon = |name: &str| {
+        Path::new(name)
+            .file_name()
+            .and_then(|binary| desktop_icons.get(binary.to_str()?))
+    };
+    let mut names = wanted.clone();
+    names.extend(wanted.iter().filter_map(|name| desktop_icon(name)).cloned());
+    let mut icon_map = get_icon_map(&names)?;
+    for name in wanted {
+        if icon_map.contains_key(name) {
+            continue;
+        }
+        let path = desktop_icon(name).and_then(|icon| {
+            if is_icon_file(icon) {
+                Path::new(icon).is_file().then(|| icon.clone())
+            } else {
+                icon_map.get(icon).cloned()
+            }
+        });
+        icon_map.insert(name.clone(), path.unwrap_or_default());
+    }
+    // keep the icons of the other menus, so switching between them doesn't
+    // rescan every time
+    cached.extend(icon_map);
+    save_to_cache_file(&cached)?;
+    Ok(cached)
+}
+
+/// How long a downloaded icon is used before being fetched again.
+const REMOTE_ICON_TTL: std::time::Duration = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How long to wait before downloading an icon again after a failure.
+const REMOTE_ICON_RETRY: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Whether the file exists and was modified less than `age` ago.
+fn modified_within(path: &str, age: std::time::Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|elapsed| elapsed < age)
+}
+
+/// Whether the icon is a remote URL rather than an icon name.
+fn is_remote_icon(icon: &str) -> bool {
+    icon.starts_with("https://") || icon.starts_with("http://")
+}
+
+/// Whether the icon is the path of an image file rather than an icon name.
+fn is_icon_file(icon: &str) -> bool {
+    icon.contains('/') && !is_remote_icon(icon)
+}
+
+/// Download a remote icon into the cache and return its local path. Icons
+/// are fetched again once older than the TTL, and a stale copy is used when
+/// the download fails (e.g. when offline), which is not tried again for an
+/// hour.
+fn fetch_remote_icon(url: &str) -> Option<String> {
+    let icons_dir = format!("{}/icons", cache_dir());
+    let extension = Path::new(url.split(['?', '#']).next().unwrap_or(url))
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .filter(|ext| ext.len() <= 4)
+        .unwrap_or("png");
+    let file_name: String = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let path = format!("{}/{}.{}", icons_dir, file_name, extension);
+    let exists = || Path::new(&path).exists().then(|| path.clone());
+
+    // a failed download is marked, so that every launch doesn't wait on curl
+    let failed_path = format!("{}.failed", path);
+    if modified_within(&path, REMOTE_ICON_TTL) || modified_within(&failed_path, REMOTE_ICON_RETRY) {
+        return exists();
+    }
+
+    let tmp_path = format!("{}.tmp", path);
+    let downloaded = fs::create_dir_all(&icons_dir).is_ok()
+        && Command::new("curl")
+            .args(["--fail", "--silent", "--location", "--max-time", "5"])
+            .args(["--output", &tmp_path, url])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+        && fs::rename(&tmp_path, &path).is_ok();
+    if downloaded {
+        let _ = fs::remove_file(&failed_path);
+    } else {
+        let _ = fs::remove_file(&tmp_path);
+        let _ = fs::write(&failed_path, "");
+    }
+    exists()
+}
+
+/// Return the icon name of an entry, defaulting to its binary name.
+fn entry_icon_name(mc: &RaffiConfig) -> String {
+    mc.icon
+        .clone()
+        .unwrap_or_else(|| mc.binary.clone().unwrap_or_else(|| "unknown".to_string()))
+}
+
+/// Maximum number of launches kept in the history file.
+const HISTORY_MAX_RECORDS: usize = 1000;
+
+/// Return the path of the launch history file.
+fn history_path() -> String {
+    format!("{}/history", cache_dir())
+}
+
+/// Read the launch history as (unix timestamp, entry name) records.
+fn read_history() -> Vec<(u64, String)> {
+    fs::read_to_string(history_path())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (timestamp, name) = line.split_once('\t')?;
+            Some((timestamp.parse().ok()?, name.to_string()))
+        })
+        .collect()
+}
+
+/// Append a launch of the entry to the history, keeping only the latest records.
+fn record_launch(name: &str) -> Result<()> {
+    let mut history = read_history();
+    history.push((unix_now(), name.to_string()));
+    let skip = history.len().saturating_sub(HISTORY_MAX_RECORDS);
+    let contents: String = history
+        .iter()
+        .skip(skip)
+        .map(|(timestamp, name)| format!("{}\t{}\n", timestamp, name))
+        .collect();
+    fs::create_dir_all(cache_dir()).context("Failed to create cache directory")?;
+    fs::write(history_path(), contents).context("Failed to write history file")
+}
+
+/// Return the current unix timestamp in seconds.
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Sort entries by how likely they are to be launched now: every past launch
+/// counts more when it happened at a similar time of day and recently.
+fn sort_by_suggestion(rafficonfigs: &mut [RaffiConfig], history: &[(u64, String)]) {
+    const DAY: f64 = 24.0 * 60.0 * 60.0;
+    const RECENCY_HALF_LIFE: f64 = 14.0 * DAY;
+    let now = unix_now() as f64;
+    let mut scores: HashMap<&str, f64> = HashMap::new();
+    for (timestamp, name) in history {
+        let timestamp = *timestamp as f64;
+        let hours = (now - timestamp).rem_euclid(DAY) / 3600.0;
+        let hours_apart = hours.min(24.0 - hours);
+        let time_of_day = (-(hours_apart * hours_apart) / 8.0).exp();
+        let recency = 0.5_f64.powf((now - timestamp).max(0.0) / RECENCY_HALF_LIFE);
+        *scores.entry(name).or_default() += time_of_day * recency;
+    }
+    rafficonfigs.sort_by(|a, b| {
+        let score = |mc: &RaffiConfig| scores.get(mc.name.as_str()).copied().unwrap_or(0.0);
+        score(b).total_cmp(&score(a))
     });
+}
+
+/// Split the entries sorted by description in pages of at most `page_size`
+/// entries, each labelled with the range of initials it covers.
+fn paginate_entries(
+    mut rafficonfigs: Vec<RaffiConfig>,
+    page_size: usize,
+) -> Vec<(String, Vec<RaffiConfig>)> {
+    rafficonfigs.sort_by_key(|mc| entry_description(mc).to_lowercase());
+    let mut pages = Vec::new();
+    let mut entries = rafficonfigs.into_iter().peekable();
+    while entries.peek().is_some() {
+        let page: Vec<RaffiConfig> = entries.by_ref().take(page_size.max(1)).collect();
+        let initial = |mc: &RaffiConfig| {
+            entry_description(mc)
+                .chars()
+                .next()
+                .map(|c| c.to_uppercase().to_string())
+                .unwrap_or_default()
+        };
+        let (first, last) = (initial(&page[0]), initial(&page[page.len() - 1]));
+        let label = if first == last {
+            format!("{} ({})", first, page.len())
+        } else {
+            format!("{} – {} ({})", first, last, page.len())
+        };
+        pages.push((label, page));
+    }
+    pages
+}
+
+/// Return the description shown for an entry in the launcher.
+fn entry_description(mc: &RaffiConfig) -> &str {
+    mc.description
+        .as_deref()
+        .unwrap_or_else(|| mc.binary.as_deref().unwrap_or("unknown"))
+}
 
+/// Resolve the icon path of every entry.
+fn resolve_icons(rafficonfigs: &mut [RaffiConfig]) -> Result<()> {
+    let icon_map = read_icon_map(
+        &rafficonfigs
+            .iter()
+            .map(entry_icon_name)
+            .filter(|icon| !is_remote_icon(icon) && !is_icon_file(icon))
+            .collect(),
+    )?;
+    for mc in rafficonfigs {
+        let icon = entry_icon_name(mc);
+        mc.icon_path = if is_remote_icon(&icon) {
+            fetch_remote_icon(&icon)
+        } else if is_icon_file(&icon) {
+            Path::new(&icon).is_file().then_some(icon)
+        } else {
+            icon_map.get(&icon).filter(|path| !path.is_empty()).cloned()
+        };
+    }
+    Ok(())
+}
+
+/// Make a text fit on a fuzzel line: newlines would split the entry and the
+/// `\0` and `\x1f` of the row options corrupt it, so control characters are
+/// replaced with spaces.
+fn fuzzel_field(text: &str) -> Cow<'_, str> {
+    let text = text.trim();
+    if text.contains(char::is_control) {
+        Cow::Owned(text.replace(char::is_control, " "))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// Create the input for fuzzel based on the Raffi configurations, with a
+/// header line starting every group. Also return the index of the entry of
+/// every line, None for the headers.
+fn make_fuzzel_input(rafficonfigs: &[RaffiConfig], no_icons: bool) -> (String, Vec<Option<usize>>) {
+    let mut ret = String::new();
+    let mut lines = Vec::with_capacity(rafficonfigs.len());
+    let mut group = None;
+    for (index, mc) in rafficonfigs.iter().enumerate() {
+        if mc.group.is_some() && mc.group.as_ref() != group {
+            ret.push_str(&format!(
+                "── {} ──\n",
+                fuzzel_field(mc.group.as_deref().unwrap_or_default())
+            ));
+            lines.push(None);
+        }
+        group = mc.group.as_ref();
+        ret.push_str(&fuzzel_field(entry_description(mc)));
+        // fuzzel only matches the text of the line, its only row option is
+        // the icon
+        let keywords = mc.keywords.as_deref().unwrap_or_default().join(" ");
+        if !keywords.is_empty() {
+            ret.push_str(" · ");
+            ret.push_str(&fuzzel_field(&keywords));
+        }
+        if !no_icons {
+            ret.push_str("\0icon\x1f");
+            ret.push_str(&fuzzel_field(mc.icon_path.as_deref().unwrap_or("default")));
+        }
+        ret.push('\n');
+        lines.push(Some(index));
+    }
+    (ret, lines)
+}
+
+/// Keep the entries of a group together, in the order the groups first
+/// appear, the entries without a group coming first.
+fn group_entries(rafficonfigs: &mut [RaffiConfig]) {
+    let mut groups: Vec<String> = Vec::new();
+    for group in rafficonfigs.iter().filter_map(|mc| mc.group.as_ref()) {
+        if !groups.contains(group) {
+            groups.push(group.clone());
+        }
+    }
+    rafficonfigs.sort_by_key(|mc| {
+        mc.group
+            .as_ref()
+            .and_then(|group| groups.iter().position(|known| known == group))
+            .map_or(0, |position| position + 1)
+    });
+}
+
+/// An entry as printed by `raffi list --json`.
+#[derive(Serialize)]
+struct ListedEntry<'a> {
+    name: &'a str,
+    description: &'a str,
+    binary: Option<&'a str>,
+    args: &'a [String],
+    script: Option<&'a str>,
+    icon: Option<&'a str>,
+}
+
+impl<'a> From<&'a RaffiConfig> for ListedEntry<'a> {
+    fn from(mc: &'a RaffiConfig) -> Self {
+        ListedEntry {
+            name: &mc.name,
+            description: entry_description(mc),
+            binary: mc.binary.as_deref(),
+            args: mc.args.as_deref().unwrap_or_default(),
+            script: mc.script.as_deref(),
+            icon: mc.icon_path.as_deref(),
+        }
+    }
+}
+
+/// Print the entries, as JSON or as name and description lines.
+fn list_entries(mut rafficonfigs: Vec<RaffiConfig>, json: bool, no_icons: bool) -> Result<()> {
+    if !json {
+        for mc in &rafficonfigs {
+            println!("{}\t{}", mc.name, entry_description(mc));
+        }
+        return Ok(());
+    }
+
+    if !no_icons {
+        resolve_icons(&mut rafficonfigs)?;
+    }
+    let entries: Vec<ListedEntry> = rafficonfigs.iter().map(ListedEntry::from).collect();
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&entries).context("Failed to serialize entries")?
+    );
+    Ok(())
+}
+
+/// Execute the chosen command or script.
+fn execute_chosen_command(
+    mc: &RaffiConfig,
+    settings: &Settings,
+    args: &Args,
+    interpreter: &str,
+    kind: MenuKind,
+) -> Result<()> {
+    let plan = mc
+        .commands
+        .as_deref()
+        .map(|commands| command_plan(commands, mc.stop_on_failure.unwrap_or(false)));
+    if args.print_json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&ListedEntry::from(mc))
+                .context("Failed to serialize entry")?
+        );
+        return Ok(());
+    }
     if args.print_only {
-        if let Some(script) = &mc.script {
-            println!("#!/usr/bin/env -S {}\n{}", interpreter_with_args, script);
+        if let Some(plan) = &plan {
+            println!("{}", plan);
+        } else if let Some(script) = &mc.script {
+            println!("#!/usr/bin/env -S {}", interpreter);
+            if let Some(script_args) = mc.args.as_deref().filter(|args| !args.is_empty()) {
+                // shells get their $1..$N back, other interpreters a comment
+                let shell = interpreter
+                    .split_whitespace()
+                    .next()
+                    .and_then(|program| Path::new(program).file_name())
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| ["sh", "bash", "zsh", "dash", "ksh"].contains(&name));
+                let prefix = if shell { "set --" } else { "# args:" };
+                println!("{} {}", prefix, shell_join(script_args));
+            }
+            println!("{}", script);
         } else {
+            let binary = mc.binary.as_ref().context("Binary not found")?;
             println!(
-                "{} {}",
-                mc.binary.as_deref().context("Binary not found")?,
-                mc.args.as_deref().unwrap_or(&[]).join(" ")
+                "{}",
+                shell_join(std::iter::once(binary).chain(mc.args.iter().flatten()))
             );
         }
         return Ok(());
     }
-    if let Some(script) = &mc.script {
+    if mc.webapp.is_some() {
+        fs::create_dir_all(webapp_profile_dir(&mc.name))
+            .context("Failed to create webapp profile directory")?;
+    }
+    // the settings are for the configured entries, not for the helpers run
+    // by the generated menus such as wl-copy or swaymsg
+    let config = kind == MenuKind::Config;
+    let scope = mc.systemd_scope.unwrap_or(config && settings.systemd_scope) && has_systemd_user();
+    let wrapper = match &mc.sandbox {
+        Some(Sandbox::Wrapper(wrapper)) => wrapper.as_slice(),
+        Some(Sandbox::Enabled(false)) => &[],
+        Some(Sandbox::Enabled(true)) | None if config => settings.sandbox.as_slice(),
+        Some(Sandbox::Enabled(true)) | None => &[],
+    };
+    if let Some(plan) = plan {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(plan);
+        command = in_systemd_unit(in_sandbox(command, wrapper), mc, scope);
+        start_command(command, mc, args, None)?;
+    } else if let Some(script) = &mc.script {
         let mut temp_script =
             tempfile::NamedTempFile::new().context("Failed to create temp script file")?;
-        writeln!(
-            temp_script,
-            "#!/usr/bin/env -S {}\n{}",
-            interpreter_with_args, script
-        )
-        .context("Failed to write to temp script file")?;
+        writeln!(temp_script, "#!/usr/bin/env -S {}\n{}", interpreter, script)
+            .context("Failed to write to temp script file")?;
 
-        // set the script file to be executable
+        // set the script file to be executable, by the user only as it may
+        // hold secrets
         let mut permissions = temp_script
             .as_file()
             .metadata()
             .context("Failed to get metadata of temp script file")?
             .permissions();
-        permissions.set_mode(0o755);
+        permissions.set_mode(0o700);
         temp_script
             .as_file()
             .set_permissions(permissions)
@@ -297,67 +1750,1416 @@ fn execute_chosen_command(mc: &RaffiConfig, args: &Args, interpreter: &str) -> R
             .persist(&temp_script_path)
             .context("Failed to persist temp script file")?;
 
+        // the args are given to the script itself, as $1..$N in shells
         let mut command = Command::new(&temp_script_path);
-        let mut child = command.spawn().context("cannot launch script")?;
-        child.wait().context("cannot wait for child")?;
-        // remove the temp script file
-        fs::remove_file(temp_script_path.clone()).context("Failed to remove temp script file")?;
+        command.args(mc.args.iter().flatten());
+        command = in_sandbox(command, wrapper);
+        if scope {
+            command = in_systemd_scope(command, &mc.name);
+        }
+        if let Err(err) = start_command(command, mc, args, Some(&temp_script_path)) {
+            // the wrapper never ran to remove it
+            let _ = fs::remove_file(&temp_script_path);
+            return Err(err);
+        }
     } else {
         let mut command = Command::new(mc.binary.as_deref().context("Binary not found")?);
         if let Some(binary_args) = &mc.args {
             command.args(binary_args);
         }
-        let mut child = command.spawn().context("cannot launch binary")?;
-        child.wait().context("cannot wait for child")?;
+        command = in_systemd_unit(in_sandbox(command, wrapper), mc, scope);
+        start_command(command, mc, args, None)?;
     }
     Ok(())
 }
 
+/// Run a command as a restarted service when the entry asks for it, else in
+/// a scope when enabled.
+fn in_systemd_unit(command: Command, mc: &RaffiConfig, scope: bool) -> Command {
+    if let Some(restart) = mc.restart.filter(|_| has_systemd_user()) {
+        as_systemd_service(
+            command,
+            &mc.name,
+            &mc.env,
+            mc.cwd.as_deref(),
+            restart,
+            mc.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS),
+        )
+    } else if scope {
+        in_systemd_scope(command, &mc.name)
+    } else {
+        command
+    }
+}
+
+/// Quote a word for sh, words without special characters are kept as is.
+fn shell_quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Join the words in a command line that sh splits back to the same words.
+fn shell_join<'a>(words: impl IntoIterator<Item = &'a String>) -> String {
+    words
+        .into_iter()
+        .map(|word| shell_quote(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Build the sh command line running the commands one after the other,
+/// stopping at the first failure when asked.
+fn command_plan(commands: &[CommandStep], stop_on_failure: bool) -> String {
+    commands
+        .iter()
+        .map(|step| shell_join(std::iter::once(&step.binary).chain(step.args.iter().flatten())))
+        .collect::<Vec<_>>()
+        .join(if stop_on_failure { " && " } else { "; " })
+}
+
+/// Check if a systemd user instance is there to run scopes.
+fn has_systemd_user() -> bool {
+    Path::new("/run/systemd/system").exists() && find_binary("systemd-run")
+}
+
+/// Wrap a command in a sandbox command like bwrap or firejail, given as its
+/// words, the command is left as is without any.
+fn in_sandbox(command: Command, wrapper: &[String]) -> Command {
+    let Some((program, wrapper_args)) = wrapper.split_first() else {
+        return command;
+    };
+    let mut sandbox = Command::new(program);
+    sandbox
+        .args(wrapper_args)
+        .arg(command.get_program())
+        .args(command.get_args());
+    sandbox
+}
+
+/// Wrap a command to run it in an `app-raffi-<name>-<id>.scope` systemd user
+/// scope, giving it its own cgroup out of the compositor one.
+fn in_systemd_scope(command: Command, name: &str) -> Command {
+    let mut scope = Command::new("systemd-run");
+    scope
+        .args(["--user", "--scope", "--quiet", "--collect"])
+        .arg(format!("--unit={}.scope", app_unit_name(name, '-')))
+        .arg("--")
+        .arg(command.get_program())
+        .args(command.get_args());
+    scope
+}
+
+/// Wrap a command to run it as an `app-raffi-<name>@<id>.service` systemd user
+/// service, restarted by systemd at most `max_restarts` times, with the `env`
+/// variables and the `cwd` of the entry.
+fn as_systemd_service(
+    command: Command,
+    name: &str,
+    env: &[(String, String)],
+    cwd: Option<&str>,
+    restart: RestartPolicy,
+    max_restarts: u32,
+) -> Command {
+    let restart = match restart {
+        RestartPolicy::No => "no",
+        RestartPolicy::OnFailure => "on-failure",
+        RestartPolicy::Always => "always",
+    };
+    let mut service = Command::new("systemd-run");
+    service
+        .args(["--user", "--quiet", "--collect"])
+        .arg(format!("--unit={}.service", app_unit_name(name, '@')))
+        .arg(format!("--property=Restart={}", restart))
+        .arg("--property=RestartSec=1")
+        .arg("--property=StartLimitIntervalSec=infinity")
+        .arg(format!("--property=StartLimitBurst={}", max_restarts + 1));
+    // services don't inherit the session environment like scopes do
+    for var in ["PATH", "WAYLAND_DISPLAY", "DISPLAY", "XDG_CURRENT_DESKTOP"] {
+        if std::env::var_os(var).is_some() {
+            service.arg(format!("--setenv={}", var));
+        }
+    }
+    // only the names, systemd-run takes the values from its environment so
+    // they don't show in its arguments
+    for (var, _) in env {
+        service.arg(format!("--setenv={}", var));
+    }
+    // nor the working directory
+    if let Some(cwd) = cwd {
+        service.arg(format!("--working-directory={}", cwd));
+    }
+    service
+        .arg("--")
+        .arg(command.get_program())
+        .args(command.get_args());
+    service
+}
+
+/// Return the `app-raffi-<name><separator><id>` systemd unit name of a launch.
+fn app_unit_name(name: &str, separator: char) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!(
+        "app-raffi-{}{}{:x}{:x}",
+        name,
+        separator,
+        std::process::id(),
+        unix_now()
+    )
+}
+
+/// Number of stderr lines shown when a command fails in `--wait` mode.
+const STDERR_TAIL_LINES: usize = 10;
+
+/// Start the command of an entry, detached or waiting for it with `--wait`.
+fn start_command(
+    mut command: Command,
+    mc: &RaffiConfig,
+    args: &Args,
+    temp_script: Option<&str>,
+) -> Result<()> {
+    let name = &mc.name;
+    if let Some(delay) = &mc.delay {
+        let delay = parse_duration(delay).context(format!("invalid delay {}", delay))?;
+        command = delayed(command, entry_description(mc), delay);
+    }
+    // raffi doesn't wait for the script, the outermost wrapper removes it
+    // once done, even when the delay was cancelled
+    if let Some(temp_script) = temp_script {
+        command = removing_after(command, temp_script);
+    }
+    // set on the outermost command, the wrappers pass them on to the entry
+    command.envs(mc.env.iter().map(|(name, value)| (name, value)));
+    if let Some(cwd) = &mc.cwd {
+        command.current_dir(cwd);
+    }
+    if args.wait {
+        wait_and_report(command, name)
+    } else {
+        spawn_detached(command, name)
+    }
+}
+
+/// Wait for the delay before running the command, and show a notification
+/// with a Cancel action to call it off.
+const DELAY_SCRIPT: &str = r#"delay=$1 description=$2; shift 2
+sleep "$delay" &
+timer=$!
+(
+    action=$(notify-send --app-name=raffi --expire-time="${delay}000" \
+        --action=cancel=Cancel "$description in ${delay}s")
+    [ "$action" = cancel ] && kill "$timer"
+) 2>/dev/null &
+notifier=$!
+wait "$timer" || exit 0
+kill "$notifier" 2>/dev/null
+exec "$@""#;
+
+/// Wrap a command to remove a file once it exits.
+fn removing_after(command: Command, path: &str) -> Command {
+    let mut wrapper = Command::new("sh");
+    wrapper
+        .arg("-c")
+        .arg("\"$@\"; status=$?; rm -f -- \"$0\"; exit $status")
+        .arg(path)
+        .arg(command.get_program())
+        .args(command.get_args());
+    wrapper
+}
+
+/// Wrap a command to run it after a delay, cancellable from a notification.
+fn delayed(command: Command, description: &str, delay: std::time::Duration) -> Command {
+    let mut timer = Command::new("sh");
+    timer
+        .arg("-c")
+        .arg(DELAY_SCRIPT)
+        .arg("sh")
+        .arg(delay.as_secs().to_string())
+        .arg(description)
+        .arg(command.get_program())
+        .args(command.get_args());
+    timer
+}
+
+/// Run a command until it exits, and on failure show the end of its stderr in
+/// a notification, or as an error when notify-send is not available.
+fn wait_and_report(mut command: Command, name: &str) -> Result<()> {
+    let output = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|source| RaffiError::SpawnFailed {
+            command: name.to_string(),
+            source,
+        })?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines: Vec<&str> = stderr.lines().collect();
+    let tail = lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n");
+    let summary = format!("{} failed ({})", name, output.status);
+    let notified = Command::new("notify-send")
+        .args(["--app-name=raffi", "--urgency=critical", &summary, &tail])
+        .status()
+        .is_ok_and(|status| status.success());
+    if !notified {
+        anyhow::bail!("{}\n{}", summary, tail);
+    }
+    Ok(())
+}
+
+/// Spawn a command in its own process group with its stdio detached from
+/// raffi, so it doesn't get the signals of raffi or its terminal when raffi
+/// exits or gets killed.
+fn spawn_detached(mut command: Command, name: &str) -> Result<()> {
+    let child = command
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|source| RaffiError::SpawnFailed {
+            command: name.to_string(),
+            source,
+        })?;
+    // not the Command itself, its environment can hold secrets
+    tracing::info!(
+        "started {} as pid {}: {:?} {:?}",
+        name,
+        child.id(),
+        command.get_program(),
+        command.get_args().collect::<Vec<_>>()
+    );
+    Ok(())
+}
+
+/// Run raffi, exiting with the code of the failure: quietly with 130 when
+/// the menu was cancelled, 2 for config errors, 3 when the menu could not
+/// be shown, 4 when the command could not be started and 1 otherwise.
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            let kind = err
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<RaffiError>());
+            if !matches!(kind, Some(RaffiError::Cancelled)) {
+                eprintln!("Error: {:?}", err);
+            }
+            ExitCode::from(kind.map_or(1, RaffiError::exit_code))
+        }
+    }
+}
+
 /// Main function to execute the program logic.
-fn main() -> Result<()> {
-    let args = Args::parse_args_default_or_exit();
-    let configfile = args.configfile.clone().unwrap_or_else(|| {
-        format!(
-            "{}/raffi/raffi.yaml",
-            std::env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| format!(
-                "{}/.config",
-                std::env::var("HOME").unwrap_or_default()
-            ))
+fn run() -> Result<()> {
+    let mut args = Args::parse_args_default_or_exit();
+    args.print_only |= args.print_json;
+    init_logging(args.verbose);
+    // the emoji are their own icons
+    if matches!(args.command, Some(Subcommand::Emoji(_))) {
+        args.no_icons = true;
+    }
+    if args.version {
+        println!("raffi {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    let configfile = args
+        .configfile
+        .clone()
+        .or_else(|| std::env::var("RAFFI_CONFIG").ok())
+        .unwrap_or_else(find_config);
+
+    let rebuild = matches!(&args.command, Some(Subcommand::Cache(opts)) if opts.action.as_deref() == Some("rebuild"));
+    if args.refresh_cache || rebuild {
+        rebuild_caches(&configfile, &args)?;
+    }
+
+    match &args.command {
+        None | Some(Subcommand::Launch(_)) if args.stdin => launch_stdin(&configfile, &args),
+        None | Some(Subcommand::Launch(_)) => launch(&configfile, &args),
+        Some(Subcommand::List(opts)) => {
+            let mut rafficonfigs = read_config(&configfile, &args, true)?;
+            filter_group(&mut rafficonfigs, &args);
+            list_entries(rafficonfigs, opts.json, args.no_icons)
+        }
+        Some(Subcommand::Run(opts)) => {
+            let name = opts.entry.as_deref().context("missing entry name")?;
+            let Some(mc) = read_menu(&configfile, &args, true)?
+                .into_iter()
+                .find(|mc| mc.name == name)
+            else {
+                if load_config(&configfile)?.entries.contains_key(name) {
+                    anyhow::bail!(
+                        "entry {} is disabled or hidden by its conditions, see why with --show-skipped",
+                        name
+                    );
+                }
+                anyhow::bail!("no entry named {} in {}", name, configfile);
+            };
+            run_entry(
+                &mc,
+                &load_config(&configfile)?.settings,
+                &args,
+                MenuKind::Config,
+            )
+        }
+        Some(Subcommand::Validate(_)) => validate_config(&configfile, &args),
+        Some(Subcommand::Bench(opts)) => {
+            bench::run(&configfile, &args, opts.runs.unwrap_or(bench::DEFAULT_RUNS))
+        }
+        // the conditions depend on the moment the menu is shown, `show` checks
+        // them on the entries of the daemon
+        Some(Subcommand::Daemon(_)) => daemon::serve(
+            &configfile,
+            || with_icons(read_config(&configfile, &args, false)?, &args),
+            || {
+                let config = load_config(&configfile)?;
+                with_icons(generated_entries(&configfile, &config, &args, false), &args)
+            },
+        ),
+        Some(Subcommand::Show(_)) => match daemon::fetch_entries(&configfile)? {
+            Some(mut rafficonfigs) => {
+                rafficonfigs.retain(|mc| mc.conditions.failed().is_none());
+                launch_entries(
+                    rafficonfigs,
+                    &load_config(&configfile)?.settings,
+                    &args,
+                    MenuKind::Config,
+                )
+            }
+            None => launch(&configfile, &args),
+        },
+        Some(Subcommand::Session(opts)) => {
+            match opts.action.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+                ["save", name] => session::save(name),
+                ["remove", name] => session::remove(name),
+                ["list"] => {
+                    for name in session::names() {
+                        println!("{}", name);
+                    }
+                    Ok(())
+                }
+                _ => anyhow::bail!(
+                    "unknown session action, use one of: save NAME, list, remove NAME"
+                ),
+            }
+        }
+        Some(Subcommand::Clipboard(_)) => {
+            let settings = load_settings(&configfile)?;
+            let mut rafficonfigs = clipboard::entries(&settings.clipboard)?;
+            if !args.no_icons {
+                resolve_icons(&mut rafficonfigs)?;
+            }
+            launch_entries(rafficonfigs, &settings, &args, MenuKind::Generated)
+        }
+        Some(Subcommand::Emoji(_)) => launch_entries(
+            emoji::entries()?,
+            &load_settings(&configfile)?,
+            &args,
+            MenuKind::Generated,
+        ),
+        Some(Subcommand::Windows(_)) => {
+            let mut rafficonfigs = window_entries()?;
+            if !args.no_icons {
+                resolve_icons(&mut rafficonfigs)?;
+            }
+            launch_entries(
+                rafficonfigs,
+                &load_settings(&configfile)?,
+                &args,
+                MenuKind::Generated,
+            )
+        }
+        Some(Subcommand::Plugin(opts)) => {
+            let name = opts.name.as_deref().context("missing plugin name")?;
+            let mut rafficonfigs = plugin::items(name)?;
+            if !args.no_icons {
+                resolve_icons(&mut rafficonfigs)?;
+            }
+            launch_entries(
+                rafficonfigs,
+                &load_settings(&configfile)?,
+                &args,
+                MenuKind::Generated,
+            )
+        }
+        Some(Subcommand::Cache(opts)) => match opts.action.as_deref() {
+            Some("clear") => clear_caches(),
+            // done above, before anything reads the caches
+            Some("rebuild") => Ok(()),
+            Some("path") => {
+                println!("{}", cache_dir());
+                Ok(())
+            }
+            _ => anyhow::bail!("unknown cache action, use one of: clear, rebuild, path"),
+        },
+        Some(Subcommand::Migrate(_)) => migrate_config_file(&configfile),
+        Some(Subcommand::Schema(_)) => print_schema(),
+        Some(Subcommand::Completions(opts)) if opts.entries => {
+            for mc in read_menu(&configfile, &args, true)? {
+                println!("{}", mc.name);
+            }
+            Ok(())
+        }
+        Some(Subcommand::Completions(opts)) => {
+            let shell = opts.shell.as_deref().context(format!(
+                "missing shell argument, use one of: {}",
+                completions::SHELLS.join(", ")
+            ))?;
+            print!(
+                "{}",
+                completions::generate(
+                    shell,
+                    Args::usage(),
+                    Args::command_list().unwrap_or_default(),
+                    Args::command_usage,
+                )?
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Return the entries of the saved sessions, the plugins and the ssh hosts,
+/// checked and filtered by the profile like those of the configuration file.
+fn generated_entries(
+    configfile: &str,
+    config: &Config,
+    args: &Args,
+    check_conditions: bool,
+) -> Vec<RaffiConfig> {
+    let generated = session::entries()
+        .into_iter()
+        .chain(plugin::entries(configfile))
+        .chain(ssh::entries(&config.settings.ssh_hosts));
+    let mut rafficonfigs = Vec::new();
+    for mut mc in generated {
+        if !config.profile_includes(args.profile.as_deref(), &mc.name) {
+            continue;
+        }
+        if let Some(reason) = skip_reason(&mut mc, args, check_conditions) {
+            if args.show_skipped {
+                eprintln!("{}: skipped, {}", mc.name, reason);
+            }
+            tracing::debug!("{}: skipped, {}", mc.name, reason);
+            continue;
+        }
+        rafficonfigs.push(mc);
+    }
+    rafficonfigs
+}
+
+/// Read the entries of the launcher: those of the configuration file followed
+/// by the generated ones.
+fn read_menu(configfile: &str, args: &Args, check_conditions: bool) -> Result<Vec<RaffiConfig>> {
+    let mut rafficonfigs = read_config(configfile, args, check_conditions)?;
+    let config = load_config(configfile)?;
+    rafficonfigs.extend(generated_entries(
+        configfile,
+        &config,
+        args,
+        check_conditions,
+    ));
+    Ok(rafficonfigs)
+}
+
+/// Resolve the icons of the entries, unless `--no-icons` is given.
+fn with_icons(mut rafficonfigs: Vec<RaffiConfig>, args: &Args) -> Result<Vec<RaffiConfig>> {
+    if !args.no_icons && !rafficonfigs.is_empty() {
+        resolve_icons(&mut rafficonfigs)?;
+    }
+    Ok(rafficonfigs)
+}
+
+/// Read the entries shown in the launcher, with their icons resolved.
+fn load_entries(configfile: &str, args: &Args, check_conditions: bool) -> Result<Vec<RaffiConfig>> {
+    with_icons(read_menu(configfile, args, check_conditions)?, args)
+}
+
+/// Return an entry focusing every open window, described by its application
+/// and title.
+fn window_entries() -> Result<Vec<RaffiConfig>> {
+    Ok(compositor::windows()?
+        .into_iter()
+        .enumerate()
+        .map(|(index, window)| {
+            let mut focus = window.focus.into_iter();
+            RaffiConfig {
+                binary: focus.next(),
+                args: Some(focus.collect()),
+                description: Some(format!("{}: {}", window.app, window.title)),
+                icon: Some(window.app.to_lowercase()),
+                name: format!("window-{}", index),
+                ..Default::default()
+            }
+        })
+        .collect())
+}
+
+/// Return the settings of the configuration file, the defaults without one.
+fn load_settings(configfile: &str) -> Result<Settings> {
+    if Path::new(configfile).exists() {
+        Ok(load_config(configfile)?.settings)
+    } else {
+        Ok(Settings::default())
+    }
+}
+
+/// Show the launcher and run the chosen entry.
+fn launch(configfile: &str, args: &Args) -> Result<()> {
+    let settings = load_config(configfile)?.settings;
+    launch_entries(
+        load_entries(configfile, args, true)?,
+        &settings,
+        args,
+        MenuKind::Config,
+    )
+}
+
+/// Where the entries of a menu come from.
+#[derive(Clone, Copy, PartialEq)]
+enum MenuKind {
+    /// The entries of the configuration file, which typed commands can be run
+    /// from.
+    Config,
+    /// Entries generated by a subcommand or read from stdin.
+    Generated,
+}
+
+/// Show the launcher with the given entries and run the chosen one.
+fn launch_entries(
+    rafficonfigs: Vec<RaffiConfig>,
+    settings: &Settings,
+    args: &Args,
+    kind: MenuKind,
+) -> Result<()> {
+    match choose_entry(rafficonfigs, settings, args, kind)? {
+        Some(mc) => run_entry(&mc, settings, args, kind),
+        None => Err(RaffiError::Cancelled.into()),
+    }
+}
+
+/// Return the `fuzzel_args` setting followed by the `--fuzzel-arg` options,
+/// given to every fuzzel raffi runs.
+fn user_fuzzel_args(settings: &Settings, args: &Args) -> Vec<String> {
+    settings
+        .fuzzel_args
+        .iter()
+        .chain(&args.fuzzel_arg)
+        .cloned()
+        .collect()
+}
+
+/// Show the launcher with the given entries and return the chosen one.
+fn choose_entry(
+    mut rafficonfigs: Vec<RaffiConfig>,
+    settings: &Settings,
+    args: &Args,
+    kind: MenuKind,
+) -> Result<Option<RaffiConfig>> {
+    let mut fuzzel_args: Vec<String> = settings
+        .prompt
+        .iter()
+        .map(|prompt| format!("--prompt={}", prompt))
+        .chain(
+            settings
+                .placeholder
+                .iter()
+                .map(|placeholder| format!("--placeholder={}", placeholder)),
         )
-    });
+        .chain((settings.auto_select || args.auto_select).then(|| "--auto-select".to_string()))
+        .chain(user_fuzzel_args(settings, args))
+        .collect();
+    if args.group.is_some() {
+        filter_group(&mut rafficonfigs, args);
+        // a single group needs no header
+        for mc in &mut rafficonfigs {
+            mc.group = None;
+        }
+    }
+    if let Some(page_size) = args.page_size.filter(|size| rafficonfigs.len() > *size) {
+        let pages = paginate_entries(rafficonfigs, page_size);
+        let labels: String = pages
+            .iter()
+            .map(|(label, _)| format!("{}\n", label))
+            .collect();
+        let chosen = run_fuzzel_with_input(&labels, false, false, &fuzzel_args)?;
+        let page = match chosen {
+            Some(Selection::Line(index)) => pages.into_iter().nth(index),
+            _ => None,
+        };
+        match page {
+            Some((_, page)) => rafficonfigs = page,
+            None => return Ok(None),
+        }
+    }
+
+    // generated menus keep their order, and out of the history
+    let sort = match kind {
+        MenuKind::Config => sort_mode(settings, args),
+        MenuKind::Generated => SortMode::Config,
+    };
+    match sort {
+        SortMode::Alpha => {
+            rafficonfigs.sort_by_cached_key(|mc| entry_description(mc).to_lowercase())
+        }
+        SortMode::Frecency => sort_by_suggestion(&mut rafficonfigs, &read_history()),
+        SortMode::Config | SortMode::Mru => {}
+    }
 
-    if args.refresh_cache {
-        refresh_icon_cache()?;
+    group_entries(&mut rafficonfigs);
+    let custom = settings.allow_custom_command && kind == MenuKind::Config;
+    if custom {
+        disambiguate_descriptions(&mut rafficonfigs);
+    }
+    let (inputs, lines) = make_fuzzel_input(&rafficonfigs, args.no_icons);
+    if let Some(select) = &args.select {
+        fuzzel_args.insert(0, select_arg(select, &rafficonfigs, &lines));
     }
+    let chosen = run_fuzzel_with_input(&inputs, sort == SortMode::Mru, custom, &fuzzel_args)?;
+    Ok(match chosen {
+        // choosing a group header runs nothing
+        Some(Selection::Line(line)) => lines
+            .get(line)
+            .copied()
+            .flatten()
+            .and_then(|index| rafficonfigs.into_iter().nth(index)),
+        Some(Selection::Text(command)) => Some(RaffiConfig {
+            description: Some(command.clone()),
+            script: Some(command),
+            name: "custom-command".to_string(),
+            ..Default::default()
+        }),
+        None => None,
+    })
+}
 
-    let rafficonfigs = read_config(&configfile, &args)?;
-    let inputs = make_fuzzel_input(&rafficonfigs, args.no_icons)?;
-    let ret = run_fuzzel_with_input(&inputs)?;
-    let chosen = ret
-        .split(':')
-        .last()
-        .context("Failed to split input")?
-        .trim();
+/// Read the menu items from stdin, one `label[\ticon[\tcommand]]` per line.
+/// Items with a command run it with the default script shell, the others
+/// are printed on stdout when chosen, like dmenu.
+fn read_stdin_entries() -> Result<Vec<RaffiConfig>> {
+    let input = std::io::read_to_string(std::io::stdin())
+        .context("Failed to read the menu items from stdin")?;
+    Ok(input
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let label = fields.next().unwrap_or_default().to_string();
+            let icon = fields.next().filter(|icon| !icon.is_empty());
+            let command = fields.next().filter(|command| !command.is_empty());
+            RaffiConfig {
+                name: label.clone(),
+                description: Some(label),
+                icon: icon.map(expand_tilde),
+                script: command.map(String::from),
+                ..Default::default()
+            }
+        })
+        .collect())
+}
 
-    for mc in rafficonfigs {
-        let description = mc
-            .description
-            .as_deref()
-            .unwrap_or_else(|| mc.binary.as_deref().unwrap_or("unknown"));
-        if description == chosen {
-            let interpreter = mc
-                .binary
-                .clone()
-                .unwrap_or_else(|| args.default_script_shell.clone());
-            execute_chosen_command(&mc, &args, &interpreter)?;
+/// Show the launcher with the items read from stdin.
+fn launch_stdin(configfile: &str, args: &Args) -> Result<()> {
+    let settings = load_settings(configfile)?;
+    let mut rafficonfigs = read_stdin_entries()?;
+    if !args.no_icons {
+        resolve_icons(&mut rafficonfigs)?;
+    }
+    match choose_entry(rafficonfigs, &settings, args, MenuKind::Generated)? {
+        Some(mc) if mc.script.is_some() => run_entry(&mc, &settings, args, MenuKind::Generated),
+        Some(mc) => {
+            println!("{}", mc.name);
+            Ok(())
+        }
+        None => Err(RaffiError::Cancelled.into()),
+    }
+}
+
+/// Add the entry name to the descriptions shown more than once: when typed
+/// commands are allowed, the chosen entry is found from its text as fuzzel
+/// can't print both the index and the typed text.
+fn disambiguate_descriptions(rafficonfigs: &mut [RaffiConfig]) {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for mc in rafficonfigs.iter() {
+        *counts
+            .entry(fuzzel_field(entry_description(mc)).into_owned())
+            .or_default() += 1;
+    }
+    for mc in rafficonfigs.iter_mut() {
+        let description = fuzzel_field(entry_description(mc)).into_owned();
+        if counts[&description] > 1 {
+            mc.description = Some(format!("{} ({})", description, mc.name));
+        }
+    }
+}
+
+/// Keep only the entries of the group given with `--group`.
+fn filter_group(rafficonfigs: &mut Vec<RaffiConfig>, args: &Args) {
+    if let Some(group) = &args.group {
+        rafficonfigs.retain(|mc| mc.group.as_ref() == Some(group));
+        tracing::info!("{} entries in group {}", rafficonfigs.len(), group);
+    }
+}
+
+/// Return the fuzzel argument for `--select`: highlighting the first line of
+/// an entry whose name matches the text as a glob, or else starting the search
+/// with the text.
+fn select_arg(select: &str, rafficonfigs: &[RaffiConfig], lines: &[Option<usize>]) -> String {
+    lines
+        .iter()
+        .position(|index| {
+            index.is_some_and(|index| conditions::glob_match(select, &rafficonfigs[index].name))
+        })
+        .map(|line| format!("--select-index={}", line))
+        .unwrap_or_else(|| format!("--search={}", select))
+}
+
+/// Return the order of the menu, `--sort` and `--suggest` overriding the
+/// `sort` setting.
+fn sort_mode(settings: &Settings, args: &Args) -> SortMode {
+    match args.sort {
+        Some(sort) => sort,
+        None if args.suggest => SortMode::Frecency,
+        None => settings.sort,
+    }
+}
+
+/// Ask the form, secret and prompts of a configuration entry and fill them
+/// in, with the clipboard and the `@file` args.
+fn prepare_entry(mc: &mut RaffiConfig, args: &Args, fuzzel_args: &[String]) -> Result<()> {
+    // like the other prompts, --print-only shows the placeholders as they are
+    if let Some(fields) = mc.form.clone().filter(|_| !args.print_only) {
+        let entry_args = mc.args.iter().flatten().chain(
+            mc.commands
+                .iter()
+                .flatten()
+                .flat_map(|step| step.args.iter().flatten()),
+        );
+        form::check_passwords(&fields, entry_args.map(String::as_str))?;
+        if let Some(script) = &mc.script {
+            form::check_script(&fields, script)?;
+        }
+        let Some(values) = form::ask(&fields, fuzzel_args)? else {
+            return Err(RaffiError::Cancelled.into());
+        };
+        fill_args(mc, |text| form::substitute(text, &values));
+        mc.env.extend(
+            values
+                .into_iter()
+                .map(|(name, value)| (form::env_name(&name), value)),
+        );
+    }
+    if let Some(secret) = mc.prompt_secret.as_ref().filter(|_| !args.print_only) {
+        let Some(value) = form::ask_secret(secret, fuzzel_args)? else {
+            return Err(RaffiError::Cancelled.into());
+        };
+        mc.env.push((secret.env.clone(), value));
+    }
+    // --print-only shows the placeholders as they are, without asking
+    let prompts = form::placeholders(entry_texts(mc));
+    if !prompts.is_empty() && !args.print_only {
+        let Some(values) = form::ask(&prompts, fuzzel_args)? else {
+            return Err(RaffiError::Cancelled.into());
+        };
+        fill_entry(mc, |text| form::fill_placeholders(text, &values));
+    }
+    // the clipboard may hold anything, it must not become code: scripts read
+    // it from the environment and it can't be an arg of inline code
+    if mc
+        .script
+        .as_deref()
+        .is_some_and(|script| script.contains(clipboard::PLACEHOLDER))
+    {
+        anyhow::bail!(
+            "{} can't be used in scripts, read the {} environment variable instead",
+            clipboard::PLACEHOLDER,
+            clipboard::ENV
+        );
+    }
+    let mut use_clipboard = mc
+        .script
+        .as_deref()
+        .is_some_and(|script| script.contains(clipboard::ENV));
+    let commands = std::iter::once((mc.binary.as_deref(), mc.args.as_deref())).chain(
+        mc.commands
+            .iter()
+            .flatten()
+            .map(|step| (Some(step.binary.as_str()), step.args.as_deref())),
+    );
+    for (binary, command_args) in commands {
+        let command_args = command_args.unwrap_or_default();
+        if !command_args
+            .iter()
+            .any(|arg| arg.contains(clipboard::PLACEHOLDER))
+        {
+            continue;
+        }
+        if binary.is_some_and(|binary| clipboard::runs_inline_code(binary, command_args)) {
+            anyhow::bail!(
+                "{} can't be used in the args of {} -c, read the {} environment variable instead",
+                clipboard::PLACEHOLDER,
+                binary.unwrap_or_default(),
+                clipboard::ENV
+            );
+        }
+        use_clipboard = true;
+    }
+    if use_clipboard {
+        let contents = clipboard::contents()?;
+        fill_entry(mc, |text| text.replace(clipboard::PLACEHOLDER, &contents));
+        mc.env.push((clipboard::ENV.to_string(), contents));
+    }
+    if let Some(entry_args) = &mc.args {
+        mc.args = Some(expand_arg_files(entry_args)?);
+    }
+    for step in mc.commands.iter_mut().flatten() {
+        if let Some(step_args) = &step.args {
+            step.args = Some(expand_arg_files(step_args)?);
+        }
+    }
+    Ok(())
+}
+
+/// Run an entry, recording the configuration entries in the history when
+/// suggestions are enabled.
+fn run_entry(mc: &RaffiConfig, settings: &Settings, args: &Args, kind: MenuKind) -> Result<()> {
+    let fuzzel_args = user_fuzzel_args(settings, args);
+    if !args.print_only && !confirm_entry(mc, &fuzzel_args)? {
+        return Err(RaffiError::Cancelled.into());
+    }
+    let mut mc = mc.clone();
+    // generated entries hold arbitrary text such as clipboard lines or plugin
+    // ids, which must not be taken for placeholders or @files
+    if kind == MenuKind::Config {
+        prepare_entry(&mut mc, args, &fuzzel_args)?;
+    }
+    let mc = &mc;
+    if kind == MenuKind::Config
+        && sort_mode(settings, args) == SortMode::Frecency
+        && !args.print_only
+    {
+        record_launch(&mc.name)?;
+    }
+    if let (Some(track), Some(track_command)) = (&mc.track, &settings.track_command) {
+        if !args.print_only {
+            // a broken time tracker should not prevent launching the entry
+            if let Err(err) = track_launch(track_command, track) {
+                eprintln!("{}: {:#}", mc.name, err);
+            }
+        }
+    }
+    if mc
+        .notify
+        .unwrap_or(kind == MenuKind::Config && settings.notify)
+        && !args.print_only
+    {
+        // the notification is only a hint, the entry runs without it
+        if let Err(err) = notify_launch(mc) {
+            eprintln!("{}: {:#}", mc.name, err);
+        }
+    }
+    let interpreter = mc
+        .binary
+        .clone()
+        .unwrap_or_else(|| args.default_script_shell.clone());
+    execute_chosen_command(mc, settings, args, &interpreter, kind)
+}
+
+/// Ask for confirmation when the entry wants it, true when it can run.
+fn confirm_entry(mc: &RaffiConfig, user_args: &[String]) -> Result<bool> {
+    let question = match &mc.confirm {
+        None | Some(Confirm::Enabled(false)) => return Ok(true),
+        Some(Confirm::Enabled(true)) => format!("{}?", entry_description(mc)),
+        Some(Confirm::Message(message)) => message.clone(),
+    };
+    let prompt = format!("--prompt={} ", question);
+    let fuzzel_args: Vec<&str> = user_args
+        .iter()
+        .map(String::as_str)
+        .chain([prompt.as_str()])
+        .collect();
+    let answer = run_fuzzel("No\nYes\n", &fuzzel_args)?;
+    Ok(answer.is_some_and(|answer| answer.trim() == "Yes"))
+}
+
+/// Return the args, script and command args of an entry, where the form
+/// values and placeholders are substituted.
+fn entry_texts(mc: &RaffiConfig) -> Vec<&str> {
+    mc.args
+        .iter()
+        .flatten()
+        .chain(mc.script.iter())
+        .chain(
+            mc.commands
+                .iter()
+                .flatten()
+                .flat_map(|step| step.args.iter().flatten()),
+        )
+        .map(String::as_str)
+        .collect()
+}
+
+/// Replace the `@file` args with the lines of the file, one arg per non empty
+/// line. `@@` starts an arg with a literal `@`.
+fn expand_arg_files(entry_args: &[String]) -> Result<Vec<String>> {
+    let mut expanded = Vec::with_capacity(entry_args.len());
+    for arg in entry_args {
+        if let Some(literal) = arg.strip_prefix("@@") {
+            expanded.push(format!("@{}", literal));
+        } else if let Some(path) = arg.strip_prefix('@') {
+            let path = expand_tilde(path);
+            let contents =
+                fs::read_to_string(&path).context(format!("cannot read args file {}", path))?;
+            expanded.extend(
+                contents
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(str::to_string),
+            );
+        } else {
+            expanded.push(arg.clone());
+        }
+    }
+    Ok(expanded)
+}
+
+/// Rewrite the args, script and command args of an entry.
+fn fill_entry(mc: &mut RaffiConfig, fill: impl Fn(&str) -> String) {
+    if let Some(script) = &mut mc.script {
+        *script = fill(script);
+    }
+    fill_args(mc, fill);
+}
+
+/// Rewrite the args and command args of an entry, leaving its script alone.
+fn fill_args(mc: &mut RaffiConfig, fill: impl Fn(&str) -> String) {
+    for arg in mc.args.iter_mut().flatten() {
+        *arg = fill(arg);
+    }
+    for step in mc.commands.iter_mut().flatten() {
+        for arg in step.args.iter_mut().flatten() {
+            *arg = fill(arg);
+        }
+    }
+}
+
+/// Run the track command with the track value of the launched entry, without
+/// waiting for it.
+fn track_launch(track_command: &str, track: &str) -> Result<()> {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(format!("{} \"$@\"", track_command))
+        .arg("sh")
+        .arg(track);
+    spawn_detached(command, track_command)
+}
+
+/// Show a "Launching <description>" notification with the entry icon, without
+/// waiting for it.
+fn notify_launch(mc: &RaffiConfig) -> Result<()> {
+    let mut command = Command::new("notify-send");
+    command
+        .arg("--app-name=raffi")
+        .arg(format!(
+            "--icon={}",
+            mc.icon_path.clone().unwrap_or_else(|| entry_icon_name(mc))
+        ))
+        .arg(format!("Launching {}", entry_description(mc)));
+    spawn_detached(command, "notify-send")
+}
+
+/// Check that every entry of the configuration file parses, and report how
+/// many entries are shown or hidden by their conditions.
+fn validate_config(filename: &str, args: &Args) -> Result<()> {
+    let config = load_config(filename)?;
+    let (mut shown, mut hidden, mut invalid) = (0, 0, 0);
+    for (name, value) in &config.profile_entries(args.profile.as_deref())? {
+        if !value.is_mapping() {
+            continue;
+        }
+        if let Err(err) = check_fields(name, value, &config.settings) {
+            invalid += 1;
+            eprintln!("{}", err);
+            continue;
+        }
+        match serde_yaml::from_value::<RaffiConfig>(value.clone()) {
+            Ok(mut mc) => {
+                mc.name = name.clone();
+                expand_entry(&mut mc);
+                if let Some(reason) = skip_reason(&mut mc, args, true) {
+                    if args.show_skipped {
+                        eprintln!("{}: skipped, {}", name, reason);
+                    }
+                    hidden += 1;
+                } else {
+                    shown += 1;
+                }
+            }
+            Err(source) => {
+                invalid += 1;
+                let err = RaffiError::ConfigParse {
+                    entry: name.clone(),
+                    line: entry_line(filename, name),
+                    source,
+                };
+                eprintln!("{:#}", anyhow::Error::from(err));
+            }
         }
     }
+    println!("{}: {} entries shown, {} hidden", filename, shown, hidden);
+    if invalid > 0 {
+        anyhow::bail!("{} invalid entries in {}", invalid, filename);
+    }
+    Ok(())
+}
+
+/// Print the JSON schema of the configuration file.
+#[cfg(feature = "schema")]
+fn print_schema() -> Result<()> {
+    let mut schema = schemars::schema_for!(Config);
+    if let Some(metadata) = schema.schema.metadata.as_mut() {
+        metadata.title = Some("raffi configuration".to_string());
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&schema).context("Failed to serialize schema")?
+    );
+    Ok(())
+}
+
+/// The schema is compiled out.
+#[cfg(not(feature = "schema"))]
+fn print_schema() -> Result<()> {
+    anyhow::bail!("raffi was built without the schema feature")
+}
+
+/// Remove the icon caches, the launch history and fuzzel's MRU are kept.
+fn clear_caches() -> Result<()> {
+    refresh_icon_cache()?;
+    let icons_dir = format!("{}/icons", cache_dir());
+    if Path::new(&icons_dir).exists() {
+        fs::remove_dir_all(&icons_dir).context("Failed to remove remote icons cache")?;
+    }
+    Ok(())
+}
+
+/// Remove the caches and build them again for the entries of the
+/// configuration file: the icon cache and the downloaded remote icons.
+fn rebuild_caches(configfile: &str, args: &Args) -> Result<()> {
+    clear_caches()?;
+    let mut rafficonfigs = read_config(configfile, args, true)?;
+    resolve_icons(&mut rafficonfigs)?;
+    tracing::info!(
+        "caches rebuilt, {} of {} entries have an icon",
+        rafficonfigs
+            .iter()
+            .filter(|mc| mc.icon_path.is_some())
+            .count(),
+        rafficonfigs.len()
+    );
     Ok(())
 }
 
-/// Refresh the icon cache.
+/// Refresh the icon cache, icons are looked up again on the next menu.
 fn refresh_icon_cache() -> Result<()> {
-    let icon_map = get_icon_map()?;
-    save_to_cache_file(&icon_map)?;
+    let cache_path = icon_cache_path();
+    if Path::new(&cache_path).exists() {
+        fs::remove_file(&cache_path).context("Failed to remove icon cache file")?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(description: &str, group: Option<&str>) -> RaffiConfig {
+        RaffiConfig {
+            description: Some(description.to_string()),
+            group: group.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn icon_cache_header(count: u32) -> Vec<u8> {
+        let mut data = ICON_CACHE_MAGIC.to_vec();
+        data.extend_from_slice(&ICON_CACHE_VERSION.to_le_bytes());
+        data.extend_from_slice(&count.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn icon_cache_round_trip() {
+        let map = HashMap::from([
+            ("firefox".to_string(), "/icons/firefox.png".to_string()),
+            ("missing".to_string(), String::new()),
+        ]);
+        assert_eq!(decode_icon_cache(&encode_icon_cache(&map)).unwrap(), map);
+    }
+
+    #[test]
+    fn icon_cache_rejects_truncated_data() {
+        let map = HashMap::from([("firefox".to_string(), "/icons/firefox.png".to_string())]);
+        let data = encode_icon_cache(&map);
+        for len in 0..data.len() {
+            assert!(decode_icon_cache(&data[..len]).is_err(), "length {}", len);
+        }
+    }
+
+    #[test]
+    fn icon_cache_rejects_bogus_count() {
+        assert!(decode_icon_cache(&icon_cache_header(u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn icon_cache_rejects_other_formats() {
+        assert!(decode_icon_cache(b"{\"firefox\": \"/icons/firefox.png\"}").is_err());
+        let mut data = icon_cache_header(0);
+        data[4] = 2;
+        assert!(decode_icon_cache(&data).is_err());
+    }
+
+    #[test]
+    fn fuzzel_field_keeps_plain_text() {
+        assert!(matches!(fuzzel_field("Firefox"), Cow::Borrowed("Firefox")));
+        assert_eq!(fuzzel_field("  Firefox \n"), "Firefox");
+    }
+
+    #[test]
+    fn fuzzel_field_replaces_control_characters() {
+        assert_eq!(fuzzel_field("two\nlines"), "two lines");
+        assert_eq!(fuzzel_field("a\0icon\x1fb"), "a icon b");
+        assert_eq!(fuzzel_field("tab\there"), "tab here");
+    }
+
+    #[test]
+    fn make_fuzzel_input_one_line_per_entry() {
+        let mut mc = entry("two\nlines\0with\x1fjunk", None);
+        mc.icon_path = Some("/icons/a.png".to_string());
+        mc.keywords = Some(vec!["web\nbrowser".to_string()]);
+        let (input, lines) = make_fuzzel_input(&[mc, entry("b", None)], false);
+        assert_eq!(
+            input,
+            "two lines with junk · web browser\0icon\x1f/icons/a.png\nb\0icon\x1fdefault\n"
+        );
+        assert_eq!(lines, vec![Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn make_fuzzel_input_without_icons() {
+        let (input, _) = make_fuzzel_input(&[entry("a", None)], true);
+        assert_eq!(input, "a\n");
+    }
+
+    #[test]
+    fn make_fuzzel_input_group_headers() {
+        let entries = [
+            entry("a", Some("Web")),
+            entry("b", Some("Web")),
+            entry("c", Some("Dev\nTools")),
+            entry("d", None),
+        ];
+        let (input, lines) = make_fuzzel_input(&entries, true);
+        assert_eq!(input, "── Web ──\na\nb\n── Dev Tools ──\nc\nd\n");
+        assert_eq!(lines, vec![None, Some(0), Some(1), None, Some(2), Some(3)]);
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn shell_quote_only_quotes_special_words() {
+        assert_eq!(shell_quote("firefox"), "firefox");
+        assert_eq!(shell_quote("--app=https://a.b/c"), "--app=https://a.b/c");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("two words"), "'two words'");
+        assert_eq!(shell_quote("it's $HOME"), "'it'\\''s $HOME'");
+    }
+
+    #[test]
+    fn shell_join_splits_back_to_the_words() {
+        let words = strings(&["echo", "a b", "it's", ""]);
+        assert_eq!(shell_join(&words), "echo 'a b' 'it'\\''s' ''");
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(conditions::glob_match("*", ""));
+        assert!(conditions::glob_match("web-*", "web-01"));
+        assert!(conditions::glob_match("web-??", "web-01"));
+        assert!(conditions::glob_match("*.example.*", "db.example.com"));
+        assert!(conditions::glob_match("a*b*c", "aXbYbZc"));
+        assert!(!conditions::glob_match("web-?", "web-01"));
+        assert!(!conditions::glob_match("*.org", "example.com"));
+        assert!(!conditions::glob_match("", "a"));
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        use std::time::Duration;
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration(" 30s "), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("15m"), Some(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(
+            parse_duration("1d"),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_duration("1w"),
+            Some(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn parse_duration_rejects_invalid() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("s"), None);
+        assert_eq!(parse_duration("5y"), None);
+        assert_eq!(parse_duration("-5s"), None);
+        assert_eq!(parse_duration("99999999999999999w"), None);
+    }
+
+    #[test]
+    fn expand_vars_set_and_unset() {
+        std::env::set_var("RAFFI_TEST_EXPAND", "/opt/x");
+        assert_eq!(expand_vars("$RAFFI_TEST_EXPAND/bin", false), "/opt/x/bin");
+        assert_eq!(expand_vars("${RAFFI_TEST_EXPAND}bin", false), "/opt/xbin");
+        assert_eq!(
+            expand_vars("$RAFFI_TEST_UNSET $1 $", false),
+            "$RAFFI_TEST_UNSET $1 $"
+        );
+        assert_eq!(
+            expand_vars("${RAFFI_TEST_EXPAND", false),
+            "${RAFFI_TEST_EXPAND"
+        );
+        assert_eq!(
+            expand_vars("$RAFFI_TEST_EXPAND ${RAFFI_TEST_EXPAND}", true),
+            "$RAFFI_TEST_EXPAND /opt/x"
+        );
+    }
+
+    #[test]
+    fn form_substitute_in_a_single_pass() {
+        let values = HashMap::from([
+            ("name".to_string(), "{other}".to_string()),
+            ("other".to_string(), "x".to_string()),
+        ]);
+        assert_eq!(
+            form::substitute("hi {name} {other}", &values),
+            "hi {other} x"
+        );
+        assert_eq!(
+            form::substitute("{unknown} {name", &values),
+            "{unknown} {name"
+        );
+    }
+
+    #[test]
+    fn form_prompt_placeholders() {
+        let texts = [
+            "{{prompt:host}} {{prompt:port}}",
+            "\\{{prompt:kept}} {{prompt:host}}",
+        ];
+        let names: Vec<String> = form::placeholders(texts)
+            .into_iter()
+            .map(|field| field.name)
+            .collect();
+        assert_eq!(names, strings(&["host", "port"]));
+        let values = HashMap::from([("host".to_string(), "{{prompt:port}}".to_string())]);
+        assert_eq!(
+            form::fill_placeholders(texts[1], &values),
+            "{{prompt:kept}} {{prompt:port}}"
+        );
+    }
+
+    #[test]
+    fn entry_line_of_entries_only() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "version: 2\nsettings:\n  firefox: x\nentries:\n  # firefox:\n  firefox:\n    args: [firefox]\n  \"chat app\":\n    binary: chat\nother: 1\n"
+        )
+        .unwrap();
+        let path = file.path().to_str().unwrap();
+        assert_eq!(entry_line(path, "firefox"), Some(6));
+        assert_eq!(entry_line(path, "chat app"), Some(8));
+        assert_eq!(entry_line(path, "args"), None);
+        assert_eq!(entry_line(path, "other"), None);
+    }
+
+    #[test]
+    fn entry_line_version_1() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "firefox:\n  binary: firefox\nchat:\n  binary: chat\n").unwrap();
+        let path = file.path().to_str().unwrap();
+        assert_eq!(entry_line(path, "chat"), Some(3));
+        assert_eq!(entry_line(path, "binary"), None);
+    }
+
+    #[test]
+    fn migrate_config_version_1() {
+        let value: Value = serde_yaml::from_str("firefox:\n  binary: firefox\nstray: 1\n").unwrap();
+        let migrated = migrate_config(value).unwrap();
+        assert_eq!(migrated["version"].as_u64(), Some(CONFIG_VERSION));
+        assert_eq!(
+            migrated["entries"]["firefox"]["binary"].as_str(),
+            Some("firefox")
+        );
+        assert!(migrated["entries"].get("stray").is_none());
+    }
+
+    #[test]
+    fn migrate_config_current_and_future_versions() {
+        let value: Value = serde_yaml::from_str("version: 2\nentries: {}\n").unwrap();
+        assert_eq!(migrate_config(value.clone()).unwrap(), value);
+        let value: Value = serde_yaml::from_str("version: 99\n").unwrap();
+        let err = migrate_config(value).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RaffiError>(),
+            Some(RaffiError::ConfigInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn runs_inline_code_of_interpreters() {
+        use clipboard::runs_inline_code;
+        assert!(runs_inline_code(
+            "sh",
+            &strings(&["-c", "echo {clipboard}"])
+        ));
+        assert!(runs_inline_code("/bin/bash", &strings(&["-euc", "x"])));
+        assert!(runs_inline_code("python3", &strings(&["-c", "x"])));
+        assert!(runs_inline_code("node", &strings(&["--eval", "x"])));
+        assert!(runs_inline_code("perl", &strings(&["-e", "x"])));
+        assert!(!runs_inline_code("sh", &strings(&["script.sh", "x"])));
+        assert!(!runs_inline_code("sh", &strings(&["--norc", "x"])));
+        assert!(!runs_inline_code("mpv", &strings(&["-c", "x"])));
+    }
+
+    #[test]
+    fn unknown_fields_of_an_entry() {
+        let value: Value = serde_yaml::from_str(
+            "binary: x\nifenvset: X\nprompt_secret: {env: X}\ndescriptoin: x\n",
+        )
+        .unwrap();
+        assert_eq!(unknown_fields(&value), strings(&["descriptoin"]));
+    }
+}
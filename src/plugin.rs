@@ -0,0 +1,97 @@
+//! Plugins: executables in the plugins directory providing their own menu.
+//!
+//! `<plugin> --list` prints one item per line as `id<TAB>label[<TAB>icon]`,
+//! and `<plugin> --run <id>` acts on the chosen item. Every plugin is shown
+//! as an entry opening the menu of its items.
+
+use std::{fs, process::Command};
+
+use anyhow::{Context, Result};
+
+use crate::{is_executable, RaffiConfig};
+
+/// Return the directory the plugins are looked up in.
+fn plugins_dir() -> String {
+    format!("{}/raffi/plugins", crate::config_home())
+}
+
+fn plugin_path(name: &str) -> String {
+    format!("{}/{}", plugins_dir(), name)
+}
+
+/// Return the names of the executable files in the plugins directory.
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(plugins_dir())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| is_executable(&entry.path()))
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Return an entry for every plugin, running `raffi plugin <name>` to show
+/// the plugin menu.
+pub fn entries(configfile: &str) -> Vec<RaffiConfig> {
+    let Some(raffi) = std::env::current_exe()
+        .ok()
+        .and_then(|path| path.to_str().map(str::to_string))
+    else {
+        return Vec::new();
+    };
+    names()
+        .into_iter()
+        .map(|name| RaffiConfig {
+            binary: Some(raffi.clone()),
+            args: Some(vec![
+                "--configfile".to_string(),
+                configfile.to_string(),
+                "plugin".to_string(),
+                name.clone(),
+            ]),
+            description: Some(name.clone()),
+            icon: Some("application-x-addon".to_string()),
+            name: format!("plugin-{}", name),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Ask the plugin for its items, each one running `<plugin> --run <id>`.
+pub fn items(name: &str) -> Result<Vec<RaffiConfig>> {
+    let path = plugin_path(name);
+    let output = Command::new(&path)
+        .arg("--list")
+        .output()
+        .context(format!("cannot run plugin {}", name))?;
+    if !output.status.success() {
+        anyhow::bail!("plugin {} --list failed with {}", name, output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let id = fields.next().filter(|id| !id.is_empty())?;
+            let label = fields
+                .next()
+                .filter(|label| !label.is_empty())
+                .unwrap_or(id);
+            Some(RaffiConfig {
+                binary: Some(path.clone()),
+                args: Some(vec!["--run".to_string(), id.to_string()]),
+                description: Some(label.to_string()),
+                icon: Some(
+                    fields
+                        .next()
+                        .filter(|icon| !icon.is_empty())
+                        .unwrap_or("application-x-addon")
+                        .to_string(),
+                ),
+                name: id.to_string(),
+                ..Default::default()
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,104 @@
+//! Sessions: the command lines of the applications open on the compositor,
+//! saved under a name and shown as "Restore session <name>" entries.
+
+use std::fs;
+
+use anyhow::{Context, Result};
+
+use crate::{compositor, shell_quote, RaffiConfig};
+
+/// Return the directory the sessions are saved in.
+fn sessions_dir() -> String {
+    format!("{}/raffi/sessions", crate::state_home())
+}
+
+/// Return the file of a session, names being kept inside the sessions
+/// directory.
+fn session_path(name: &str) -> Result<String> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        anyhow::bail!(
+            "invalid session name {}, it can't contain / or start with .",
+            name
+        );
+    }
+    Ok(format!("{}/{}.json", sessions_dir(), name))
+}
+
+/// Read the command line of a process.
+fn process_command(pid: u32) -> Option<Vec<String>> {
+    let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let argv: Vec<String> = cmdline
+        .split(|byte| *byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    (!argv.is_empty()).then_some(argv)
+}
+
+/// Save the command lines of the open windows as a session.
+pub fn save(name: &str) -> Result<()> {
+    let mut commands: Vec<Vec<String>> = Vec::new();
+    for pid in compositor::window_pids()? {
+        if let Some(argv) = process_command(pid) {
+            // applications with several windows are launched once
+            if !commands.contains(&argv) {
+                commands.push(argv);
+            }
+        }
+    }
+    fs::create_dir_all(sessions_dir()).context("Failed to create sessions directory")?;
+    let contents = serde_json::to_string_pretty(&commands).context("cannot serialize session")?;
+    fs::write(session_path(name)?, contents).context(format!("cannot write session {}", name))?;
+    println!("saved {} applications in session {}", commands.len(), name);
+    Ok(())
+}
+
+/// Return the names of the saved sessions.
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(sessions_dir())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .and_then(|file| file.strip_suffix(".json"))
+                .map(str::to_string)
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// Remove a saved session.
+pub fn remove(name: &str) -> Result<()> {
+    fs::remove_file(session_path(name)?).context(format!("no session named {}", name))
+}
+
+/// Return a "Restore session <name>" entry for every saved session, starting
+/// all of its applications in the background.
+pub fn entries() -> Vec<RaffiConfig> {
+    names()
+        .into_iter()
+        .filter_map(|name| {
+            let contents = fs::read_to_string(session_path(&name).ok()?).ok()?;
+            let commands: Vec<Vec<String>> = serde_json::from_str(&contents).ok()?;
+            let script: String = commands
+                .iter()
+                .map(|argv| {
+                    let words: Vec<String> = argv.iter().map(|word| shell_quote(word)).collect();
+                    format!("{} &\n", words.join(" "))
+                })
+                .collect();
+            Some(RaffiConfig {
+                binary: Some("sh".to_string()),
+                description: Some(format!("Restore session {}", name)),
+                icon: Some("view-restore".to_string()),
+                script: Some(script),
+                name: format!("session-{}", name),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,125 @@
+//! SSH hosts: "SSH to <host>" entries for the hosts of the ssh configuration
+//! and of the known hosts file, opening ssh in `$TERMINAL`.
+
+use std::{collections::BTreeSet, fs};
+
+use crate::{conditions::glob_match, RaffiConfig};
+
+fn ssh_dir() -> String {
+    format!("{}/.ssh", std::env::var("HOME").unwrap_or_default())
+}
+
+/// Whether a `Host` value is a pattern rather than a host to connect to.
+fn is_host_pattern(host: &str) -> bool {
+    host.contains(['*', '?', '!'])
+}
+
+/// Return the files matching an `Include` argument, relative paths being in
+/// `~/.ssh` and `*` or `?` being allowed in the file name.
+fn include_files(argument: &str) -> Vec<String> {
+    let path = crate::expand_tilde(argument);
+    let path = if path.starts_with('/') {
+        path
+    } else {
+        format!("{}/{}", ssh_dir(), path)
+    };
+    if !is_host_pattern(&path) {
+        return vec![path];
+    }
+    let (dir, pattern) = path.rsplit_once('/').unwrap_or((".", &path));
+    let mut files: Vec<String> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .filter(|name| glob_match(pattern, name))
+        .map(|name| format!("{}/{}", dir, name))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Add the hosts of an ssh configuration file and of the files it includes.
+fn config_hosts(path: &str, hosts: &mut BTreeSet<String>, depth: usize) {
+    // ssh itself gives up on deeply nested includes
+    if depth > 16 {
+        return;
+    }
+    let Ok(contents) = fs::read_to_string(path) else {
+        return;
+    };
+    for line in contents.lines() {
+        // keywords are case insensitive and may be followed by `=`
+        let Some((keyword, rest)) = line
+            .trim()
+            .split_once(|c: char| c.is_whitespace() || c == '=')
+        else {
+            continue;
+        };
+        let keyword = keyword.to_ascii_lowercase();
+        let words = rest
+            .trim_start_matches(|c: char| c.is_whitespace() || c == '=')
+            .split_whitespace();
+        match keyword.as_str() {
+            "host" => hosts.extend(
+                words
+                    .filter(|host| !is_host_pattern(host))
+                    .map(str::to_string),
+            ),
+            "include" => {
+                for argument in words {
+                    for file in include_files(argument) {
+                        config_hosts(&file, hosts, depth + 1);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Add the hosts of a known hosts file, hashed ones and hosts on another port
+/// are skipped.
+fn known_hosts(path: &str, hosts: &mut BTreeSet<String>) {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return;
+    };
+    for line in contents.lines() {
+        let Some(names) = line.split_whitespace().next() else {
+            continue;
+        };
+        if names.starts_with(['#', '|', '@', '[']) {
+            continue;
+        }
+        hosts.extend(
+            names
+                .split(',')
+                .filter(|host| !host.starts_with('[') && !is_host_pattern(host))
+                .map(str::to_string),
+        );
+    }
+}
+
+/// Return an "SSH to <host>" entry for every host matching one of the glob
+/// patterns, none without patterns.
+pub fn entries(patterns: &[String]) -> Vec<RaffiConfig> {
+    if patterns.is_empty() {
+        return Vec::new();
+    }
+    let mut hosts = BTreeSet::new();
+    config_hosts(&format!("{}/config", ssh_dir()), &mut hosts, 0);
+    known_hosts(&format!("{}/known_hosts", ssh_dir()), &mut hosts);
+    let terminal = std::env::var("TERMINAL").unwrap_or_else(|_| "xterm".to_string());
+    hosts
+        .into_iter()
+        .filter(|host| patterns.iter().any(|pattern| glob_match(pattern, host)))
+        .map(|host| RaffiConfig {
+            binary: Some(terminal.clone()),
+            args: Some(vec!["-e".to_string(), "ssh".to_string(), host.clone()]),
+            description: Some(format!("SSH to {}", host)),
+            icon: Some("utilities-terminal".to_string()),
+            name: format!("ssh-{}", host),
+            ..Default::default()
+        })
+        .collect()
+}